@@ -1,24 +1,65 @@
-use html2text::from_read_with_decorator;
-use html2text::render::text_renderer::TrivialDecorator;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::PathBuf,
+use crate::cfi::Cfi;
+use crate::container::Container;
+use crate::error::{EpubError, Result};
+use crate::fragment;
+use crate::nav::Nav;
+use crate::opf::{
+    is_external, resolve_href, Item, Opf, OpfMetaData, SpineItems, DEFAULT_SPINE_STEP,
 };
+use crate::resource::{Resources, ZipReader};
+use crate::styled::{self, StyledText};
+use crate::toc::Ncx;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
+// The parsed structure of a book, its files are read on demand. Reading state such as the
+// selected chapter belongs to the reader.
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Book {
-    pub path: PathBuf,
-    pub title: String,
-    pub author: String,
-    pub language: String,
-    pub date: String,
-    pub toc: Vec<Toc>,
-    pub selected: usize,
-    pub context: String,
-    pub flat_toc: Vec<FlatToc>,
+    // 打开的 epub 文件
+    #[serde(default)]
+    pub(crate) source: PathBuf,
+    // opf 文件所在的目录, 所有 href 都相对于它
+    #[serde(default)]
+    pub(crate) root: String,
+    pub(crate) title: String,
+    pub(crate) author: String,
+    pub(crate) language: String,
+    pub(crate) date: String,
+    pub(crate) toc: Vec<Toc>,
+    pub(crate) flat_toc: Vec<FlatToc>,
+    // spine 顺序的文档列表, 连续阅读模式按它翻页
+    #[serde(default)]
+    pub(crate) spine: Vec<SpineItems>,
+    // 读取书籍文件的后端, 压缩包或解压后的目录
+    #[serde(skip)]
+    pub(crate) resources: Resources,
+    #[serde(default)]
+    pub(crate) manifest: Vec<Item>,
+    // opf 中的完整元数据
+    #[serde(default)]
+    pub(crate) metadata: OpfMetaData,
+    // 封面图片的路径, 相对于 opf 目录; 没有封面时为空
+    #[serde(default)]
+    pub(crate) cover: String, // CFI 中 spine 的步数, 旧的索引中没有时使用通常的 /6
+    #[serde(default = "default_spine_step")]
+    pub(crate) spine_step: usize,
+}
+
+fn default_spine_step() -> usize {
+    DEFAULT_SPINE_STEP
+}
+
+// Where a resolved link inside the book points to
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LinkTarget {
+    pub path: String,
+    // 文件中存在的锚点, 不存在时为空
+    pub id: String,
+    // 锚点所在的大纲条目, 优先使用锚点本身对应的条目
+    pub entry: Option<usize>,
+    pub spine_index: Option<usize>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -32,127 +73,433 @@ pub struct Toc {
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Anchor {
     pub id: String,
-    pub start_pos: usize,
-    pub end_pos: usize,
+    // 同一文件中按文档顺序的下一个大纲锚点, 本节内容到它为止; 为空时读到文件末尾
+    pub end_id: String,
 }
 
 impl Book {
-    // Read the chapter file
-    pub fn read_and_show_text(&mut self) {
-        if self.selected >= self.flat_toc.len() {
-            return;
+    // Open an EPUB file, files are read from the archive on demand
+    pub fn open(book_path: &str) -> Result<Book> {
+        let mut book = Book::from_resources(Resources::new(ZipReader::open(book_path)?))?;
+        book.source = PathBuf::from(book_path);
+        Ok(book)
+    }
+
+    // Parse the package, TOC and spine of a book
+    pub fn from_resources(resources: Resources) -> Result<Book> {
+        let root_file = Container::read_root_file(&resources)?;
+
+        let mut book = Book {
+            // 所有 href 都相对于 opf 文件所在的目录
+            root: match root_file.rfind('/') {
+                Some(index) => root_file[..index].to_string(),
+                None => "".to_string(),
+            },
+            resources: resources.clone(),
+            ..Default::default()
+        };
+
+        // 读取 opf 文件
+        let opf = Opf::parse(&resources.read_to_string(&root_file)?).map_err(|e| {
+            EpubError::InvalidOpf {
+                path: root_file.clone(),
+                message: e.to_string(),
+            }
+        })?;
+
+        // EPUB 3 的导航文档优先于 NCX
+        if let Some(nav_item) = opf.nav_item() {
+            if resources.exists(&book.full_path(&nav_item.path())) {
+                let text = book.read_text(&nav_item.path())?;
+                book.toc = Nav::parse(&text, &nav_item.path()).toc;
+            }
         }
 
-        let toc = &self.flat_toc[self.selected];
-        let file_path = self.path.join("OEBPS").join(&toc.path);
-
-        if file_path.exists() {
-            let file = File::open(&file_path).unwrap();
-            let reader = BufReader::new(file);
-            let start = toc.anchor.start_pos;
-            let end = toc.anchor.end_pos;
-            let content: String;
-
-            if start == 0 && end == 0 {
-                // If start and end are 0, read the entire file
-                content = reader
-                    .lines()
-                    .map(|line| line.unwrap())
-                    .collect::<Vec<String>>()
-                    .join("\n");
-            } else {
-                // Only read the lines in the specified range
-                content = reader
-                    .lines()
-                    .enumerate()
-                    .skip(start)
-                    .take(end - start)
-                    .map(|(_, line)| line.unwrap())
-                    .collect::<Vec<String>>()
-                    .join("\n");
+        let ncx_item = opf.ncx_item();
+
+        if let Some(ncx_item) = ncx_item {
+            if book.toc.is_empty() && resources.exists(&book.full_path(&ncx_item.path())) {
+                let ncx_path = book.full_path(&ncx_item.path());
+                let text = book.read_text(&ncx_item.path())?;
+
+                // 生成大纲
+                let ncx = Ncx::parse(&text).map_err(|e| EpubError::InvalidXml {
+                    path: ncx_path,
+                    message: e.to_string(),
+                })?;
+                book.toc = ncx
+                    .nav_map
+                    .nav_point
+                    .iter()
+                    .map(|nav_point| nav_point.get_toc(&ncx_item.path()))
+                    .collect();
             }
+        }
+
+        // 指出声明了却缺失的文件, 否则指出 opf 文件
+        let toc_path = opf
+            .nav_item()
+            .or(ncx_item)
+            .map(|item| book.full_path(&item.path()))
+            .unwrap_or(root_file);
 
-            // self.context = html_to_text(&content);
+        let cover = opf.cover_href();
 
-            let pure_text =
-                from_read_with_decorator(content.as_bytes(), 1400, TrivialDecorator::new());
+        let metadata = opf.package.metadata;
+        book.title = metadata.title.clone();
+        book.language = metadata.language.clone();
+        book.date = metadata.date.clone();
+        book.author = metadata.author();
+        book.metadata = metadata;
+        book.manifest = opf.package.manifest.item;
+        book.spine = opf.spine_items;
+        book.spine_step = opf.spine_step;
 
-            self.context = pure_text;
+        // 没有大纲时按 spine 生成, 连续阅读不需要大纲; 连 spine 也没有时无法阅读
+        if book.toc.is_empty() {
+            if book.spine.is_empty() {
+                return Err(EpubError::MissingToc { path: toc_path });
+            }
+            book.toc = book.spine_toc();
         }
+        book.cover = cover
+            .map(|href| book.cover_image_path(&href))
+            .unwrap_or_default();
+
+        book.generate_anchor_positions();
+        book.flatten_toc();
+
+        Ok(book)
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
     }
 
-    pub fn generate_anchor_positions(&mut self) -> Result<(), std::io::Error> {
-        let mut anchor_positions = Vec::new();
+    pub fn author(&self) -> &str {
+        &self.author
+    }
 
-        // Collect the positions of all anchors
-        for toc in &self.toc {
-            self.collect_anchor_positions(&toc, &mut anchor_positions)?;
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn date(&self) -> &str {
+        &self.date
+    }
+
+    // The EPUB file the book was opened from
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    pub fn set_source(&mut self, source: PathBuf) {
+        self.source = source;
+    }
+
+    // Read the files from another backend, e.g. after loading a saved index
+    pub fn set_resources(&mut self, resources: Resources) {
+        self.resources = resources;
+    }
+
+    pub fn metadata(&self) -> &OpfMetaData {
+        &self.metadata
+    }
+
+    pub fn manifest(&self) -> &[Item] {
+        &self.manifest
+    }
+
+    pub fn spine(&self) -> &[SpineItems] {
+        &self.spine
+    }
+
+    pub fn toc(&self) -> &[Toc] {
+        &self.toc
+    }
+
+    // The TOC in reading order, every entry is a section of a content file
+    pub fn flat_toc(&self) -> &[FlatToc] {
+        &self.flat_toc
+    }
+
+    // Path of the cover image relative to the opf directory
+    pub fn cover(&self) -> Option<&str> {
+        Some(self.cover.as_str()).filter(|cover| !cover.is_empty())
+    }
+
+    pub fn cover_image(&self) -> Result<Vec<u8>> {
+        match self.cover() {
+            Some(cover) => self.read_bytes(cover),
+            None => Err(EpubError::MissingResource {
+                path: "cover".to_string(),
+            }),
         }
+    }
+
+    // Plain text of the flattened TOC entry at `index`
+    pub fn chapter_text(&self, index: usize) -> Result<String> {
+        self.chapter_styled(index).map(|text| text.to_string())
+    }
+
+    // Styled lines of the flattened TOC entry at `index`
+    pub fn chapter_styled(&self, index: usize) -> Result<StyledText> {
+        let toc = self.flat_toc.get(index).ok_or(EpubError::MissingResource {
+            path: format!("#{}", index),
+        })?;
+
+        self.section_styled(&toc.path, &toc.anchor.id, &toc.anchor.end_id)
+    }
+
+    // Plain text of the whole spine document at `index`
+    pub fn spine_text(&self, index: usize) -> Result<String> {
+        self.spine_styled(index).map(|text| text.to_string())
+    }
+
+    // Styled lines of the whole spine document at `index`
+    pub fn spine_styled(&self, index: usize) -> Result<StyledText> {
+        let item = self.spine.get(index).ok_or(EpubError::MissingResource {
+            path: format!("spine #{}", index),
+        })?;
+
+        self.section_styled(&item.href, "", "")
+    }
 
-        let mut temp_toc = std::mem::take(&mut self.toc);
+    // The spine index of a content file, `href` is relative to the opf directory
+    pub fn spine_position(&self, href: &str) -> Option<usize> {
+        self.spine.iter().position(|item| item.href == href)
+    }
+
+    // The CFI of a byte offset in the plain text of the spine document at `index`, see
+    // `spine_text`
+    pub fn cfi_for_position(&self, index: usize, offset: usize) -> Result<String> {
+        let item = self.spine.get(index).ok_or(EpubError::MissingResource {
+            path: format!("spine #{}", index),
+        })?;
+        let text = self.section_styled(&item.href, "", "")?;
+
+        Ok(self.cfi(index, &item.id, &text, offset))
+    }
 
-        // 为每个 TOC 项设置 start_pos 和 end_pos
-        for toc in &mut temp_toc {
-            self.process_toc_item(toc, &anchor_positions)?;
+    // The spine document and the byte offset in its plain text a CFI points to
+    pub fn resolve_cfi(&self, cfi: &str) -> Result<(usize, usize)> {
+        let parsed: Cfi = cfi.parse()?;
+        let index = self.cfi_spine_index(&parsed).ok_or(EpubError::InvalidCfi {
+            cfi: cfi.to_string(),
+        })?;
+        let text = self.section_styled(&self.spine[index].href, "", "")?;
+
+        // 指向元素而且后面没有显示的文字时(例如只有脚注的文档)使用文档开头
+        let offset = parsed
+            .offset_in(&text)
+            .or_else(|| parsed.offset.is_none().then_some(0))
+            .ok_or(EpubError::InvalidCfi {
+                cfi: cfi.to_string(),
+            })?;
+        Ok((index, offset))
+    }
+
+    // The CFI of a byte offset in `text`, a section of the spine document at `index`
+    pub fn cfi_in(&self, index: usize, text: &StyledText, offset: usize) -> Option<String> {
+        let item = self.spine.get(index)?;
+        Some(self.cfi(index, &item.id, text, offset))
+    }
+
+    fn cfi(&self, index: usize, idref: &str, text: &StyledText, offset: usize) -> String {
+        Cfi {
+            spine_step: self.spine_step,
+            ..Cfi::from_text(index, idref, text, offset)
+        }
+        .to_string()
+    }
+
+    // The byte offset in `text`, a section of the spine document at `index`, a CFI points
+    // to. None when it is in another document or outside the section
+    pub fn cfi_offset_in(&self, cfi: &str, index: usize, text: &StyledText) -> Option<usize> {
+        let cfi: Cfi = cfi.parse().ok()?;
+        if self.cfi_spine_index(&cfi)? != index {
+            return None;
         }
 
-        self.toc = temp_toc;
+        cfi.offset_in(text)
+    }
+
+    // idref 优先, 书籍更新后 spine 的顺序可能改变
+    fn cfi_spine_index(&self, cfi: &Cfi) -> Option<usize> {
+        self.spine
+            .iter()
+            .position(|item| !cfi.idref.is_empty() && item.id == cfi.idref)
+            .or(Some(cfi.spine_index).filter(|&i| i < self.spine.len()))
+    }
 
-        Ok(())
+    // Resolve a manifest path to the path inside the epub
+    pub fn full_path(&self, href: &str) -> String {
+        if self.root.is_empty() {
+            href.to_string()
+        } else {
+            format!("{}/{}", self.root, href)
+        }
     }
 
-    fn collect_anchor_positions(
-        &self,
-        toc: &Toc,
-        positions: &mut Vec<(String, usize)>,
-    ) -> Result<(), std::io::Error> {
-        let path = self.path.join("OEBPS").join(&toc.path);
-        let file = File::open(&path)?;
-        let reader = BufReader::new(file);
+    // Read a content file by its manifest path
+    pub fn read_text(&self, href: &str) -> Result<String> {
+        self.resources.read_to_string(&self.full_path(href))
+    }
 
-        let re = Regex::new(r#"id="([^"]*)""#).unwrap();
+    // Read a binary file, e.g. an image, by its manifest path
+    pub fn read_bytes(&self, href: &str) -> Result<Vec<u8>> {
+        self.resources.read(&self.full_path(href))
+    }
 
-        for (num, line) in reader.lines().enumerate() {
-            let line = line?;
-            if let Some(caps) = re.captures(&line) {
-                if let Some(id) = caps.get(1) {
-                    positions.push((id.as_str().to_string(), num));
-                }
-            }
+    // The manifest entry of a file, `href` is relative to the opf directory
+    pub fn manifest_item(&self, href: &str) -> Option<&Item> {
+        self.manifest.iter().find(|item| item.path() == href)
+    }
+
+    // Find what a resolved link of a chapter points to, `href` is `path#id`. Returns None
+    // when the file is not part of the book.
+    pub fn link_target(&self, href: &str) -> Option<LinkTarget> {
+        let (path, id) = href.split_once('#').unwrap_or((href, ""));
+        let html = self.read_text(path).ok()?;
+
+        // 文件中不存在的锚点跳转到文件开头
+        let id = match fragment::element_ids(&html).iter().any(|other| other == id) {
+            true => id,
+            false => "",
+        };
+
+        let entries = || {
+            self.flat_toc
+                .iter()
+                .enumerate()
+                .filter(|(_, toc)| toc.path == path)
+        };
+        // 优先使用锚点本身对应的大纲条目, 否则使用包含它的小节
+        let entry = entries()
+            .find(|(_, toc)| toc.anchor.id == id)
+            .or_else(|| entries().find(|(_, toc)| section_contains(&html, toc, id)))
+            .or_else(|| entries().next())
+            .map(|(index, _)| index);
+
+        Some(LinkTarget {
+            path: path.to_string(),
+            id: id.to_string(),
+            entry,
+            spine_index: self.spine_position(path),
+        })
+    }
+
+    // Read the section of a content file from the element `id` to the element `end_id` as
+    // styled text, the whole file if both are empty
+    pub fn section_styled(&self, href: &str, id: &str, end_id: &str) -> Result<StyledText> {
+        let text = self.read_text(href)?;
+
+        // 锚点不存在时显示整个文件
+        let content = StyledText::from_html_section(&text, id, end_id)
+            .unwrap_or_else(|| StyledText::from_html(&text));
+
+        Ok(resolve_paths(href, content))
+    }
+
+    // Render the footnote or endnote a link of the chapter points to, `href` is the resolved
+    // `path#id` of the link
+    pub fn note(&self, href: &str) -> Result<StyledText> {
+        let (path, id) = href.split_once('#').unwrap_or((href, ""));
+        let text = self.read_text(path)?;
+
+        let html = match id {
+            "" => text,
+            id => fragment::note_html(&text, id).ok_or(EpubError::MissingResource {
+                path: href.to_string(),
+            })?,
+        };
+
+        Ok(resolve_paths(path, StyledText::from_html(&html)))
+    }
+
+    // guide 中的封面通常是一个页面, 使用页面中的第一张图片
+    fn cover_image_path(&self, href: &str) -> String {
+        let is_image = self
+            .manifest_item(href)
+            .is_some_and(|item| item.media_type.starts_with("image/"));
+        if is_image {
+            return href.to_string();
         }
 
-        Ok(())
+        self.section_styled(href, "", "")
+            .ok()
+            .and_then(|text| {
+                text.lines
+                    .into_iter()
+                    .map(|line| line.image)
+                    .find(|image| !image.is_empty() && !image.starts_with("data:"))
+            })
+            .unwrap_or_default()
+    }
+
+    // An outline with one entry per linear spine document, for books without a TOC
+    fn spine_toc(&self) -> Vec<Toc> {
+        // 全部是 linear="no" 时使用所有文档
+        let linear = self.spine.iter().any(|item| item.linear);
+        self.spine
+            .iter()
+            .filter(|item| item.linear || !linear)
+            .map(|item| Toc {
+                title: item
+                    .href
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or(&item.href)
+                    .to_string(),
+                path: item.href.clone(),
+                ..Default::default()
+            })
+            .collect()
     }
 
-    fn process_toc_item(
-        &mut self,
-        toc: &mut Toc,
-        positions: &[(String, usize)],
-    ) -> Result<(), std::io::Error> {
-        if let Some(pos) = positions.iter().find(|(id, _)| *id == toc.anchor.id) {
-            toc.anchor.start_pos = pos.1;
+    // Find where every TOC section ends: the next TOC anchor of the same file in document order
+    fn generate_anchor_positions(&mut self) {
+        let mut anchors: Vec<(String, String)> = vec![];
+        for toc in &self.toc {
+            collect_anchors(toc, &mut anchors);
+        }
+
+        // 每个文件中作为大纲锚点的 id, 按文档顺序排列
+        let mut ordered: HashMap<String, Vec<String>> = HashMap::new();
+        for (path, _) in &anchors {
+            if ordered.contains_key(path) {
+                continue;
+            }
 
-            if let Some((_, next_pos)) = positions
+            let ids = anchors
                 .iter()
-                .skip_while(|&(id, _)| *id != toc.anchor.id)
-                .nth(1)
-            {
-                toc.anchor.end_pos = *next_pos;
+                .filter(|(p, id)| p == path && !id.is_empty())
+                .map(|(_, id)| id.as_str())
+                .collect::<Vec<_>>();
+
+            let order = if ids.is_empty() {
+                vec![]
             } else {
-                toc.anchor.end_pos = self.context.lines().count();
-            }
-        }
+                // 无法读取的文件没有结束锚点, 打开章节时再报告错误
+                let mut order = self
+                    .read_text(path)
+                    .map(|html| fragment::element_ids(&html))
+                    .unwrap_or_default();
+                order.retain(|id| ids.contains(&id.as_str()));
+                order.dedup();
+                order
+            };
 
-        // 递归地处理子 TOC 项
-        for child in &mut toc.children {
-            self.process_toc_item(child, positions)?;
+            ordered.insert(path.clone(), order);
         }
 
-        Ok(())
+        for toc in &mut self.toc {
+            set_end_anchor(toc, &ordered);
+        }
     }
 
     // Flat Toc
-    pub fn flatten_toc(&mut self) {
+    fn flatten_toc(&mut self) {
         let mut flat_toc = Vec::new();
         for toc in &self.toc {
             self.flatten_toc_recursive(toc, &mut flat_toc)
@@ -173,10 +520,74 @@ impl Book {
     }
 }
 
-#[derive(Debug, Default)]
-pub struct TocPosition {
-    pub start: usize,
-    pub path: String,
+fn section_contains(html: &str, toc: &FlatToc, id: &str) -> bool {
+    styled::section_ids(html, &toc.anchor.id, &toc.anchor.end_id)
+        .is_some_and(|ids| ids.iter().any(|other| other == id))
+}
+
+// 图片和链接的地址相对于章节文件, 统一改为相对于 opf 目录
+fn resolve_paths(href: &str, mut text: StyledText) -> StyledText {
+    for line in &mut text.lines {
+        if !line.image.is_empty() && !line.image.starts_with("data:") {
+            line.image = resolve_href(href, &line.image);
+        }
+    }
+
+    for link in &mut text.links {
+        link.href = resolve_link(href, &link.href);
+    }
+
+    text
+}
+
+// http:, mailto: 等外部链接保持不变, 只有 #id 的链接指向当前文件
+fn resolve_link(base_href: &str, href: &str) -> String {
+    if is_external(href) {
+        return href.to_string();
+    }
+
+    let (path, fragment) = match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (href, None),
+    };
+    let path = match path {
+        "" => base_href.to_string(),
+        path => resolve_href(base_href, path),
+    };
+
+    match fragment {
+        Some(fragment) => format!("{}#{}", path, fragment),
+        None => path,
+    }
+}
+
+fn collect_anchors(toc: &Toc, anchors: &mut Vec<(String, String)>) {
+    anchors.push((toc.path.clone(), toc.anchor.id.clone()));
+
+    for child in &toc.children {
+        collect_anchors(child, anchors);
+    }
+}
+
+fn set_end_anchor(toc: &mut Toc, ordered: &HashMap<String, Vec<String>>) {
+    let order = ordered
+        .get(&toc.path)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    toc.anchor.end_id = match toc.anchor.id.as_str() {
+        // 没有锚点的条目从文件开头读到第一个锚点
+        "" => order.first().cloned().unwrap_or_default(),
+        id => match order.iter().position(|other| other == id) {
+            Some(index) => order.get(index + 1).cloned().unwrap_or_default(),
+            // 文件中找不到的锚点显示整个文件
+            None => String::new(),
+        },
+    };
+
+    for child in &mut toc.children {
+        set_end_anchor(child, ordered);
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -1,32 +1,46 @@
 use std::{
     fs::{self, File},
-    io::{self, BufReader},
-    path::PathBuf,
+    io::{BufReader, Read},
+    path::Path,
 };
 
 use zip::ZipArchive;
 
-pub fn dir(book_path: &str, dest_dir: &PathBuf) -> io::Result<()> {
-    let mut archive = open_epub(book_path);
+use crate::error::{EpubError, Result};
+
+// FNV-1a 64 位参数
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// Extract a book into `dest_dir`, entries that would end up outside it are skipped
+pub fn extract(book_path: &str, dest_dir: &Path) -> Result<()> {
+    let mut archive = open_epub(book_path)?;
 
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
+        let mut file = archive.by_index(i).map_err(|e| EpubError::NotZip {
+            path: book_path.to_string(),
+            message: e.to_string(),
+        })?;
 
-        let file_name = file.name().to_owned();
+        // 跳过 ../ 或绝对路径等会写到缓存目录外面的文件
+        let Some(file_name) = file.enclosed_name().map(Path::to_path_buf) else {
+            continue;
+        };
         let file_path = dest_dir.join(file_name);
+        let io_error = |e| EpubError::io(&file_path.to_string_lossy(), e);
 
         if file.is_dir() {
-            fs::create_dir_all(&file_path).expect("Failed to create directory");
+            fs::create_dir_all(&file_path).map_err(io_error)?;
         } else {
             if let Some(p) = file_path.parent() {
                 if !p.exists() {
-                    fs::create_dir_all(&p).expect("Failed to create directory");
+                    fs::create_dir_all(p).map_err(io_error)?;
                 }
             }
 
-            let mut dest_file = File::create(&file_path).expect("Failed to create file");
+            let mut dest_file = File::create(&file_path).map_err(io_error)?;
 
-            std::io::copy(&mut file, &mut dest_file).expect("Failed to copy file");
+            std::io::copy(&mut file, &mut dest_file).map_err(io_error)?;
         }
     }
     println!("解压完成, 文件缓存成功!");
@@ -34,8 +48,33 @@ pub fn dir(book_path: &str, dest_dir: &PathBuf) -> io::Result<()> {
     Ok(())
 }
 
-fn open_epub(file_path: &str) -> ZipArchive<BufReader<File>> {
-    let file = File::open(file_path).expect("Failed to open EPUB file");
+pub(crate) fn open_epub(file_path: &str) -> Result<ZipArchive<BufReader<File>>> {
+    let file = File::open(file_path).map_err(|e| EpubError::io(file_path, e))?;
     let reader = BufReader::new(file);
-    ZipArchive::new(reader).expect("Failed to read ZIP archive")
+    ZipArchive::new(reader).map_err(|e| EpubError::NotZip {
+        path: file_path.to_string(),
+        message: e.to_string(),
+    })
+}
+
+// Hash of the archive content, the cache of a book follows it when the file is renamed or moved
+pub fn content_hash(file_path: &str) -> Result<String> {
+    let io_error = |e| EpubError::io(file_path, e);
+    let mut reader = BufReader::new(File::open(file_path).map_err(io_error)?);
+    let mut buffer = [0u8; 64 * 1024];
+    let mut hash = FNV_OFFSET_BASIS;
+
+    loop {
+        let count = reader.read(&mut buffer).map_err(io_error)?;
+        if count == 0 {
+            break;
+        }
+
+        for byte in &buffer[..count] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    Ok(format!("{:016x}", hash))
 }
@@ -0,0 +1,525 @@
+use crate::error::{EpubError, Result};
+use crate::opf::DEFAULT_SPINE_STEP;
+use crate::styled::{StyledText, TextSource};
+use ego_tree::NodeRef;
+use scraper::Node;
+use std::fmt;
+use std::str::FromStr;
+
+// One step of a CFI path: an even index is the n/2-th child element, an odd index the text
+// between two child elements
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub index: usize,
+    // id 断言, 只在生成时写出, 解析时不使用
+    pub id: String,
+}
+
+// An EPUB Canonical Fragment Identifier pointing to a character of a spine document, e.g.
+// epubcfi(/6/4[chap01]!/4/10[para05]/3:10). 文档中的路径按 HTML 解析后的树计算, 解析器会补上
+// 缺少的 <head>, <body> 和 <tbody>, 所以其他阅读器按 XHTML 原文生成的 CFI 在这些位置可能对不上;
+// 本程序生成和读取的 CFI 使用同一种树, 不受影响
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Cfi {
+    // package 中 spine 元素的步数, 通常是 6
+    pub spine_step: usize,
+    pub spine_index: usize,
+    // spine 中 itemref 的 idref
+    pub idref: String,
+    // 从 <html> 开始到文本节点或元素的路径
+    pub path: Vec<Step>,
+    // 文本节点中的位置, UTF-16 单位
+    pub offset: Option<usize>,
+}
+
+impl Cfi {
+    // The CFI of a byte offset in the plain text of a rendered spine document
+    pub fn from_text(spine_index: usize, idref: &str, text: &StyledText, offset: usize) -> Self {
+        let mut cfi = Cfi {
+            spine_step: DEFAULT_SPINE_STEP,
+            spine_index,
+            idref: idref.to_string(),
+            ..Default::default()
+        };
+
+        // 位置所在的文本节点, 在第一个节点之前时使用第一个节点
+        let Some(source) = text
+            .sources
+            .iter()
+            .rev()
+            .find(|source| source.offset <= offset)
+            .or(text.sources.first())
+        else {
+            return cfi;
+        };
+
+        let plain = text.to_string();
+        let skipped = plain
+            .get(source.offset..offset.max(source.offset))
+            .map(count_visible)
+            .unwrap_or(0);
+
+        cfi.path = source.path.clone();
+        cfi.offset = Some(source.start + utf16_position(&source.text, skipped));
+        cfi
+    }
+
+    // The byte offset the CFI points to in the plain text of a rendered spine document, None
+    // when the text does not contain it, e.g. a section of the document
+    pub fn offset_in(&self, text: &StyledText) -> Option<usize> {
+        let plain = text.to_string();
+
+        if let Some(offset) = self.offset {
+            // 同一个文本节点被换行分成多段时, 使用位置所在的那一段
+            let source = text
+                .sources
+                .iter()
+                .rev()
+                .find(|source| same_path(&source.path, &self.path) && source.start <= offset);
+
+            if let Some(source) = source {
+                let visible = text_position(&source.text, offset - source.start);
+                return Some(byte_position(&plain, source.offset, visible));
+            }
+        }
+
+        // 元素或者没有渲染的文本(例如图片的替代文字): 使用它之后的第一段文本
+        let target: Vec<usize> = self.path.iter().map(|step| step.index).collect();
+        let position = text
+            .sources
+            .iter()
+            .position(|source| indices(source) >= target)?;
+
+        let source = &text.sources[position];
+        // 在这段文本之前的位置不属于它
+        if position == 0 && !indices(source).starts_with(&target) {
+            return None;
+        }
+
+        Some(source.offset)
+    }
+}
+
+impl fmt::Display for Cfi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "epubcfi(/{}/{}",
+            self.spine_step,
+            (self.spine_index + 1) * 2
+        )?;
+        if !self.idref.is_empty() {
+            write!(f, "[{}]", escape(&self.idref))?;
+        }
+
+        if !self.path.is_empty() {
+            f.write_str("!")?;
+            for step in &self.path {
+                write!(f, "/{}", step.index)?;
+                if !step.id.is_empty() {
+                    write!(f, "[{}]", escape(&step.id))?;
+                }
+            }
+            if let Some(offset) = self.offset {
+                write!(f, ":{}", offset)?;
+            }
+        }
+
+        f.write_str(")")
+    }
+}
+
+impl FromStr for Cfi {
+    type Err = EpubError;
+
+    fn from_str(cfi: &str) -> Result<Self> {
+        let invalid = || EpubError::InvalidCfi {
+            cfi: cfi.to_string(),
+        };
+
+        let inner = cfi
+            .trim()
+            .strip_prefix("epubcfi(")
+            .and_then(|inner| inner.strip_suffix(')'))
+            .ok_or_else(invalid)?;
+
+        // 范围 epubcfi(P,S,E) 只使用开始位置, 即共同的父路径 P 加上 S
+        let mut range = split_unescaped(inner, ',');
+        let inner = format!(
+            "{}{}",
+            range.next().unwrap_or_default(),
+            range.next().unwrap_or_default()
+        );
+        let mut parts = split_unescaped(&inner, '!');
+
+        let (package, _) = parse_path(parts.next().unwrap_or_default()).ok_or_else(invalid)?;
+        let (spine, item) = match package.as_slice() {
+            [spine, item, ..] if item.index >= 2 && item.index % 2 == 0 => (spine, item),
+            _ => return Err(invalid()),
+        };
+
+        let (path, offset) = match parts.next() {
+            Some(document) => parse_path(document).ok_or_else(invalid)?,
+            None => (vec![], None),
+        };
+
+        Ok(Cfi {
+            spine_step: spine.index,
+            spine_index: item.index / 2 - 1,
+            idref: item.id.clone(),
+            path,
+            offset,
+        })
+    }
+}
+
+// The CFI path of a text node from the root element, and the UTF-16 offset of the node in the
+// text between its sibling elements
+pub fn text_path(node: NodeRef<Node>) -> (Vec<Step>, usize) {
+    let mut path = vec![];
+    let mut base = 0;
+
+    // 文本节点: 前面的元素个数决定位置, 前面相连的文本节点计入偏移
+    let mut elements = 0;
+    for sibling in node.prev_siblings() {
+        match sibling.value() {
+            Node::Element(_) => elements += 1,
+            Node::Text(text) if elements == 0 => base += text.encode_utf16().count(),
+            _ => {}
+        }
+    }
+    path.push(Step {
+        index: elements * 2 + 1,
+        id: String::new(),
+    });
+
+    // 祖先元素, 不包括 <html> 本身
+    for ancestor in node.ancestors() {
+        let (Node::Element(element), Some(parent)) = (ancestor.value(), ancestor.parent()) else {
+            continue;
+        };
+        if !parent.value().is_element() {
+            break;
+        }
+
+        let before = ancestor
+            .prev_siblings()
+            .filter(|sibling| sibling.value().is_element())
+            .count();
+        path.push(Step {
+            index: (before + 1) * 2,
+            id: element.id().unwrap_or_default().to_string(),
+        });
+    }
+
+    path.reverse();
+    (path, base)
+}
+
+fn same_path(left: &[Step], right: &[Step]) -> bool {
+    left.len() == right.len() && left.iter().zip(right).all(|(l, r)| l.index == r.index)
+}
+
+fn indices(source: &TextSource) -> Vec<usize> {
+    source.path.iter().map(|step| step.index).collect()
+}
+
+// 渲染时空白会被合并, 两边只按非空白字符对应
+fn count_visible(text: &str) -> usize {
+    text.chars().filter(|c| !c.is_whitespace()).count()
+}
+
+// The UTF-16 position of the visible character number `visible` in the raw text
+fn utf16_position(text: &str, visible: usize) -> usize {
+    let mut count = 0;
+    let mut position = 0;
+
+    for c in text.chars() {
+        if !c.is_whitespace() {
+            if count == visible {
+                return position;
+            }
+            count += 1;
+        }
+        position += c.len_utf16();
+    }
+
+    position
+}
+
+// The number of visible characters before the UTF-16 position in the raw text
+fn text_position(text: &str, utf16: usize) -> usize {
+    let mut position = 0;
+
+    text.chars()
+        .take_while(|c| {
+            position += c.len_utf16();
+            position <= utf16
+        })
+        .filter(|c| !c.is_whitespace())
+        .count()
+}
+
+// The byte offset of the visible character number `visible` after `start` in the plain text
+fn byte_position(plain: &str, start: usize, visible: usize) -> usize {
+    let Some(rest) = plain.get(start..) else {
+        return plain.len();
+    };
+
+    rest.char_indices()
+        .filter(|(_, c)| !c.is_whitespace())
+        .nth(visible)
+        .map(|(index, _)| start + index)
+        .unwrap_or(plain.len())
+}
+
+// Parse `/4[body]/10/3:12`, assertions other than the id of a step are ignored
+fn parse_path(text: &str) -> Option<(Vec<Step>, Option<usize>)> {
+    let mut steps = vec![];
+    let mut offset = None;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' => {
+                let index = parse_number(&mut chars)?;
+                let id = match chars.peek() {
+                    Some('[') => parse_assertion(&mut chars)?,
+                    _ => String::new(),
+                };
+                steps.push(Step { index, id });
+            }
+            ':' => {
+                offset = Some(parse_number(&mut chars)?);
+                // 后面的文本断言和参数不影响位置
+                break;
+            }
+            // 时间和空间偏移, 例如 ~23.5 和 @100:200
+            '~' | '@' => break,
+            '[' => {
+                parse_assertion(&mut chars)?;
+            }
+            _ => return None,
+        }
+    }
+
+    (!steps.is_empty()).then_some((steps, offset))
+}
+
+fn parse_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        number.push(c);
+    }
+    number.parse().ok()
+}
+
+// `[id;s=b]`, `^` escapes the next character
+fn parse_assertion(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    chars.next_if_eq(&'[')?;
+
+    let mut value = String::new();
+    let mut in_parameters = false;
+    loop {
+        match chars.next()? {
+            '^' => {
+                let c = chars.next()?;
+                if !in_parameters {
+                    value.push(c);
+                }
+            }
+            ']' => break,
+            ';' => in_parameters = true,
+            c if !in_parameters => value.push(c),
+            _ => {}
+        }
+    }
+
+    Some(value)
+}
+
+// 按分隔符切分, 跳过被 ^ 转义的字符
+fn split_unescaped(text: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut escaped = false;
+    text.split(move |c| {
+        let split = !escaped && c == separator;
+        escaped = !escaped && c == '^';
+        split
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '^' | '[' | ']' | '(' | ')' | ',' | ';' | '=') {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::Html;
+
+    const HTML: &str = "<html><head><title>T</title></head><body>\
+        <p id=\"first\">Hello <b>bold</b> world</p><p>Second paragraph</p></body></html>";
+
+    #[test]
+    fn display_round_trip() {
+        let cfi = "epubcfi(/6/4[chap01]!/4[body]/10[para05]/3:10)";
+        let parsed: Cfi = cfi.parse().unwrap();
+
+        assert_eq!(parsed.spine_index, 1);
+        assert_eq!(parsed.idref, "chap01");
+        assert_eq!(
+            parsed
+                .path
+                .iter()
+                .map(|step| step.index)
+                .collect::<Vec<_>>(),
+            [4, 10, 3]
+        );
+        assert_eq!(parsed.offset, Some(10));
+        assert_eq!(parsed.to_string(), cfi);
+    }
+
+    #[test]
+    fn escaping() {
+        let cfi = Cfi {
+            spine_step: 6,
+            spine_index: 0,
+            idref: "a[1],b".to_string(),
+            path: vec![Step {
+                index: 4,
+                id: "x^y".to_string(),
+            }],
+            offset: None,
+        };
+
+        let text = cfi.to_string();
+        assert_eq!(text, "epubcfi(/6/2[a^[1^]^,b]!/4[x^^y])");
+        assert_eq!(text.parse::<Cfi>().unwrap(), cfi);
+    }
+
+    #[test]
+    fn spine_step() {
+        let cfi = "epubcfi(/4/2[a]!/4/2/1:0)";
+        let parsed: Cfi = cfi.parse().unwrap();
+        assert_eq!(parsed.spine_step, 4);
+        assert_eq!(parsed.to_string(), cfi);
+
+        // spine 在 manifest 之前时是 package 的第二个子元素
+        let opf = r#"<?xml version="1.0"?>
+            <package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+              <metadata/>
+              <spine><itemref idref="a"/></spine>
+              <manifest><item id="a" href="a.html" media-type="application/xhtml+xml"/></manifest>
+            </package>"#;
+        assert_eq!(crate::opf::spine_step(opf), 4);
+        assert_eq!(crate::opf::spine_step("<package><spine/></package>"), 2);
+        assert_eq!(crate::opf::spine_step("<package/>"), DEFAULT_SPINE_STEP);
+    }
+
+    #[test]
+    fn range_uses_parent_and_start() {
+        let cfi: Cfi = "epubcfi(/6/4[chap01]!/4/2,/1:3,/3:5)".parse().unwrap();
+
+        assert_eq!(
+            cfi.path.iter().map(|step| step.index).collect::<Vec<_>>(),
+            [4, 2, 1]
+        );
+        assert_eq!(cfi.offset, Some(3));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("/6/4!/4".parse::<Cfi>().is_err());
+        assert!("epubcfi(/6/3!/4)".parse::<Cfi>().is_err());
+        assert!("epubcfi(/6/4!/x)".parse::<Cfi>().is_err());
+    }
+
+    #[test]
+    fn text_path_steps() {
+        let html = Html::parse_document(HTML);
+        let text = |content: &str| {
+            html.tree
+                .root()
+                .descendants()
+                .find(|node| {
+                    node.value()
+                        .as_text()
+                        .is_some_and(|text| &**text == content)
+                })
+                .unwrap()
+        };
+
+        // 元素是偶数步, 元素之间的文本是奇数步
+        let (path, base) = text_path(text("Hello "));
+        assert_eq!(
+            path.iter().map(|step| step.index).collect::<Vec<_>>(),
+            [4, 2, 1]
+        );
+        assert_eq!(path[1].id, "first");
+        assert_eq!(base, 0);
+
+        let (path, _) = text_path(text(" world"));
+        assert_eq!(path.last().unwrap().index, 3);
+
+        let (path, _) = text_path(text("bold"));
+        assert_eq!(
+            path.iter().map(|step| step.index).collect::<Vec<_>>(),
+            [4, 2, 2, 1]
+        );
+
+        let (path, _) = text_path(text("Second paragraph"));
+        assert_eq!(
+            path.iter().map(|step| step.index).collect::<Vec<_>>(),
+            [4, 4, 1]
+        );
+    }
+
+    // 路径来自 HTML 解析的树: 缺少的 <tbody> 会被补上, 与按 XHTML 原文计算的 CFI 不同
+    #[test]
+    fn text_path_follows_the_html_tree() {
+        let html = Html::parse_document(
+            "<html><head></head><body><table><tr><td>cell</td></tr></table></body></html>",
+        );
+        let cell = html
+            .tree
+            .root()
+            .descendants()
+            .find(|node| node.value().as_text().is_some_and(|text| &**text == "cell"))
+            .unwrap();
+
+        let (path, _) = text_path(cell);
+        // body, table, tbody, tr, td, 文本
+        assert_eq!(
+            path.iter().map(|step| step.index).collect::<Vec<_>>(),
+            [4, 2, 2, 2, 2, 1]
+        );
+    }
+
+    #[test]
+    fn offset_in_text() {
+        let text = StyledText::from_html(HTML);
+        let plain = text.to_string();
+        let paragraph = plain.find("Second").unwrap();
+
+        let cfi: Cfi = "epubcfi(/6/2!/4/4/1:7)".parse().unwrap();
+        assert_eq!(cfi.offset_in(&text), Some(paragraph + 7));
+
+        // 生成的 CFI 指回同一个位置
+        let offset = plain.find("world").unwrap();
+        let cfi = Cfi::from_text(0, "c1", &text, offset);
+        assert_eq!(cfi.path.last().unwrap().index, 3);
+        let parsed: Cfi = cfi.to_string().parse().unwrap();
+        assert_eq!(parsed.offset_in(&text), Some(offset));
+
+        // 元素的路径使用它之后的第一段文本
+        let cfi: Cfi = "epubcfi(/6/2!/4/4)".parse().unwrap();
+        assert_eq!(cfi.offset_in(&text), Some(paragraph));
+    }
+}
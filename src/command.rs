@@ -0,0 +1,307 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+
+use crate::config::Config;
+use crate::explorer::highlight::Highlights;
+use crate::explorer::index::{Explorer, TOC_FILE_NAME};
+use crate::format::{format_age, format_size};
+
+// 缓存目录中一本书的信息
+struct CacheEntry {
+    key: String,
+    dir: PathBuf,
+    size: u64,
+    // 最后一次保存阅读位置的时间
+    last_read: Option<SystemTime>,
+    title: String,
+    source: String,
+}
+
+// `clean` removes the whole cache, or the cache of one book given by its file or cache key
+pub fn clean(config: &Config, target: Option<&str>) -> ExitCode {
+    let root = match config.cache_root() {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("无法找到缓存目录: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // 只删除缓存目录中的书籍, 不删除目录本身: 配置的 cache_dir 可能是其他目录, 例如 ~
+    let Some(target) = target else {
+        let entries = match entries(&root) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("无法读取 {}: {}", root.display(), e);
+                return ExitCode::FAILURE;
+            }
+        };
+        for entry in entries {
+            if let Err(e) = fs::remove_dir_all(&entry.dir) {
+                eprintln!("无法删除 {}: {}", entry.dir.display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+        println!("已清除所有缓存: {}", root.display());
+        return ExitCode::SUCCESS;
+    };
+
+    // 参数是 epub 文件时按内容找到缓存, 否则当作 `cache list` 中的缓存名
+    let key = if Path::new(target).is_file() {
+        match epub_parser::content_hash(target) {
+            Ok(key) => key,
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        target.to_string()
+    };
+
+    let dir = root.join(&key);
+    if !is_cache_dir(&dir) {
+        eprintln!("没有找到 {} 的缓存", target);
+        return ExitCode::FAILURE;
+    }
+
+    match fs::remove_dir_all(&dir) {
+        Ok(()) => {
+            println!("已清除缓存: {}", dir.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("无法删除 {}: {}", dir.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// `cache list` and `cache prune --older-than <age>`
+pub fn cache(config: &Config, args: &[String]) -> ExitCode {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        ["list"] => list(config),
+        ["prune", "--older-than", age] => match parse_age(age) {
+            Some(age) => prune(config, age),
+            None => {
+                eprintln!("无法识别的时间: {}, 例如 30d, 12h, 2w", age);
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => {
+            eprintln!("Usage: cache list | cache prune --older-than <30d|12h|2w>");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// `export <book> [markdown|json]` prints the highlights of a book
+pub fn export(config: &Config, args: &[String]) -> ExitCode {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let (target, json) = match args.as_slice() {
+        [target] | [target, "markdown" | "md"] => (*target, false),
+        [target, "json"] => (*target, true),
+        _ => {
+            eprintln!("Usage: export <path-to-epub-file> [markdown|json]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let root = match config.cache_root() {
+        Ok(root) => root,
+        Err(e) => {
+            eprintln!("无法找到缓存目录: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let dir = match epub_parser::content_hash(target) {
+        Ok(key) => root.join(key),
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // 书籍信息从索引中读取, 不需要重新解析
+    let Some(explorer) = Explorer::load(&dir) else {
+        eprintln!("没有找到 {} 的缓存, 请先打开这本书", target);
+        return ExitCode::FAILURE;
+    };
+    let highlights = Highlights::load(&dir);
+
+    let output = match json {
+        true => highlights.to_json(&explorer.book).map_err(io::Error::from),
+        false => Ok(highlights.to_markdown(&explorer.book)),
+    };
+
+    match output {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list(config: &Config) -> io::Result<()> {
+    let mut entries = entries(&config.cache_root()?)?;
+
+    if entries.is_empty() {
+        println!("没有缓存");
+        return Ok(());
+    }
+
+    // 最近阅读的在前
+    entries.sort_by_key(|entry| Reverse(entry.last_read));
+
+    let now = SystemTime::now();
+    for entry in &entries {
+        println!(
+            "{}  {:>9}  {:<10}  {}  {}",
+            entry.key,
+            format_size(entry.size),
+            entry
+                .last_read
+                .map(|time| format_age(now, time, config.language))
+                .unwrap_or_else(|| "-".to_string()),
+            entry.title,
+            entry.source
+        );
+    }
+
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    println!("共 {} 本, {}", entries.len(), format_size(total));
+
+    Ok(())
+}
+
+fn prune(config: &Config, age: Duration) -> io::Result<()> {
+    let now = SystemTime::now();
+    let mut freed = 0;
+    let mut count = 0;
+
+    for entry in entries(&config.cache_root()?)? {
+        let is_old = match entry.last_read {
+            Some(time) => now.duration_since(time).unwrap_or_default() > age,
+            None => true,
+        };
+
+        if is_old {
+            fs::remove_dir_all(&entry.dir)?;
+            println!("已清除: {} {}", entry.key, entry.title);
+            freed += entry.size;
+            count += 1;
+        }
+    }
+
+    println!("共清除 {} 本, 释放 {}", count, format_size(freed));
+
+    Ok(())
+}
+
+fn entries(root: &Path) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = vec![];
+
+    if !root.exists() {
+        return Ok(entries);
+    }
+
+    for dir in fs::read_dir(root)? {
+        let dir = dir?.path();
+        if !is_cache_dir(&dir) {
+            continue;
+        }
+
+        // 旧版本的索引读取失败时只显示目录信息
+        let explorer = Explorer::load(&dir);
+        let last_read = fs::metadata(dir.join(TOC_FILE_NAME))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        entries.push(CacheEntry {
+            key: dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            size: dir_size(&dir),
+            last_read,
+            title: explorer
+                .as_ref()
+                .map(|explorer| explorer.book.title().to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            source: explorer
+                .as_ref()
+                .map(|explorer| explorer.book.source().display().to_string())
+                .filter(|source| !source.is_empty())
+                .unwrap_or_else(|| "-".to_string()),
+            dir,
+        });
+    }
+
+    Ok(entries)
+}
+
+// 书籍的缓存目录: 名称是 16 位十六进制的内容哈希, 并且有索引文件
+fn is_cache_dir(dir: &Path) -> bool {
+    let is_key = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.len() == 16 && name.chars().all(|c| c.is_ascii_hexdigit()));
+
+    is_key && dir.join(TOC_FILE_NAME).is_file()
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return 0;
+    };
+
+    read_dir
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => dir_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+// 30d, 12h, 2w, 90m, 没有单位时按天计算
+fn parse_age(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => text.split_at(index),
+        None => (text, "d"),
+    };
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .map(|number| Duration::from_secs(number * seconds))
+}
@@ -0,0 +1,271 @@
+use crate::ui::keymap::{Action, KeySequence};
+use dirs_next::{config_dir, home_dir};
+use ratatui::style::Color;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 相对于 XDG 配置目录($XDG_CONFIG_HOME 或 ~/.config)
+const CONFIG_FILE_NAME: &str = "epub-reader/config.toml";
+// 默认的缓存目录, 相对于 home 目录
+const TEMP_DIR_NAME: &str = ".epub_reader_temp";
+
+// Reader preferences from config.toml, every field is optional
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub language: Language,
+    // 缓存目录, 支持 ~/ 开头的路径
+    pub cache_dir: Option<PathBuf>,
+    // 打开书籍时的焦点
+    pub focus: Focus,
+    pub outline: OutlineConfig,
+    pub content: ContentConfig,
+    pub theme: Theme,
+    // 动作的按键, 替换这个动作的默认按键, 例如 scroll-down = ["j", "<C-n>"]
+    pub keys: HashMap<Action, Vec<KeySequence>>,
+    // 等待组合键下一个按键的时间(毫秒), 默认 1000
+    pub key_timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    Zh,
+    En,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Focus {
+    #[default]
+    Outline,
+    Content,
+}
+
+// How ruby annotations (furigana, pinyin) are shown, also saved per book
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RubyMode {
+    // 在文字上方单独一行
+    #[default]
+    Above,
+    // 在文字后面的括号中
+    Inline,
+    Hidden,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutlineConfig {
+    pub width: u16,
+    pub visible: bool,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentConfig {
+    // 文字的最大宽度(列), 0 表示不限制, 超出时居中显示
+    pub max_width: u16,
+    // 左右两边的空白(列)
+    pub margin: u16,
+    // 行与行之间的空行数
+    pub line_spacing: usize,
+    // 按屏幕大小分页阅读, 也可以用 p 切换
+    pub paginated: bool,
+    // 段落首行缩进的字数, 中文书通常是 2
+    pub indent: usize,
+    // 在中文和英文, 数字之间加一个空格
+    pub cjk_spacing: bool,
+    // 注音的显示方式, 每本书可以用 R 切换
+    pub ruby: RubyMode,
+}
+
+// Colours of the reader, names like "lightcyan", "#rrggbb" or 256-colour indexes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    // 大纲的顶层条目和子条目
+    #[serde(deserialize_with = "color")]
+    pub outline: Color,
+    #[serde(deserialize_with = "color")]
+    pub outline_child: Color,
+    // 选中的大纲条目和列表项
+    #[serde(deserialize_with = "color")]
+    pub selected_fg: Color,
+    #[serde(deserialize_with = "color")]
+    pub selected_bg: Color,
+    // 一到四级标题, 更深的标题使用最后一个
+    #[serde(deserialize_with = "colors")]
+    pub headings: Vec<Color>,
+    #[serde(deserialize_with = "color")]
+    pub link: Color,
+    #[serde(deserialize_with = "color")]
+    pub code_fg: Color,
+    #[serde(deserialize_with = "color")]
+    pub code_bg: Color,
+    // 引用和列表的前缀
+    #[serde(deserialize_with = "color")]
+    pub prefix: Color,
+    #[serde(deserialize_with = "color")]
+    pub rule: Color,
+    // 注音
+    #[serde(deserialize_with = "color")]
+    pub ruby: Color,
+    // 弹出窗口中的标签和强调的文字
+    #[serde(deserialize_with = "color")]
+    pub label: Color,
+    #[serde(deserialize_with = "color")]
+    pub accent: Color,
+    // 高亮, 选择和搜索结果的背景
+    #[serde(deserialize_with = "color")]
+    pub highlight: Color,
+    #[serde(deserialize_with = "color")]
+    pub selection: Color,
+    #[serde(deserialize_with = "color")]
+    pub search: Color,
+}
+
+impl Default for OutlineConfig {
+    fn default() -> Self {
+        OutlineConfig {
+            width: 30,
+            visible: true,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            outline: Color::LightCyan,
+            outline_child: Color::White,
+            selected_fg: Color::White,
+            selected_bg: Color::LightBlue,
+            headings: vec![
+                Color::LightYellow,
+                Color::LightGreen,
+                Color::LightCyan,
+                Color::LightBlue,
+            ],
+            link: Color::LightBlue,
+            code_fg: Color::White,
+            code_bg: Color::DarkGray,
+            prefix: Color::Cyan,
+            rule: Color::DarkGray,
+            ruby: Color::Gray,
+            label: Color::LightCyan,
+            accent: Color::LightYellow,
+            highlight: Color::Magenta,
+            selection: Color::Blue,
+            search: Color::Yellow,
+        }
+    }
+}
+
+impl Language {
+    // Pick the text of the UI language
+    pub fn text(self, zh: &'static str, en: &'static str) -> &'static str {
+        match self {
+            Language::Zh => zh,
+            Language::En => en,
+        }
+    }
+}
+
+impl RubyMode {
+    // The next mode of the toggle key
+    pub fn next(self) -> Self {
+        match self {
+            RubyMode::Above => RubyMode::Inline,
+            RubyMode::Inline => RubyMode::Hidden,
+            RubyMode::Hidden => RubyMode::Above,
+        }
+    }
+}
+
+impl Theme {
+    pub fn heading(&self, level: u8) -> Color {
+        let index = (level as usize).saturating_sub(1);
+        self.headings
+            .get(index)
+            .or(self.headings.last())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Config {
+    // Load the config file, `path` overrides the default location. A missing default file
+    // gives the default config, a missing file that was asked for is an error.
+    pub fn load(path: Option<&Path>) -> io::Result<Config> {
+        let (path, is_default) = match path {
+            Some(path) => (path.to_path_buf(), false),
+            None => match default_path() {
+                Some(path) => (path, true),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if is_default && e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", path.display(), e),
+                ))
+            }
+        };
+
+        toml::from_str(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    // 所有书籍缓存所在的目录
+    pub fn cache_root(&self) -> io::Result<PathBuf> {
+        let home = || {
+            home_dir().ok_or(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Home directory not found",
+            ))
+        };
+
+        match &self.cache_dir {
+            Some(dir) => match dir.strip_prefix("~") {
+                Ok(rest) => Ok(home()?.join(rest)),
+                Err(_) => Ok(dir.clone()),
+            },
+            None => Ok(home()?.join(TEMP_DIR_NAME)),
+        }
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+}
+
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    name.parse()
+        .map_err(|_| de::Error::custom(format!("invalid colour: {}", name)))
+}
+
+fn colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Color>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| {
+            name.parse()
+                .map_err(|_| de::Error::custom(format!("invalid colour: {}", name)))
+        })
+        .collect()
+}
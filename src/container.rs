@@ -1,5 +1,12 @@
+use quick_xml::de::from_str;
+use quick_xml::{self, Reader};
 use serde::Deserialize;
 
+use crate::error::{EpubError, Result};
+use crate::resource::Resources;
+
+pub const CONTAINER_PATH: &str = "META-INF/container.xml";
+
 #[derive(Debug, PartialEq, Default, Deserialize)]
 pub struct Container {
     pub rootfiles: Rootfiles,
@@ -18,3 +25,36 @@ pub struct Rootfile {
     pub media_type: String,
 }
 
+impl Container {
+    pub fn parse(xml: &str) -> Result<Container> {
+        let mut reader = Reader::from_str(xml);
+        reader.trim_text(true);
+        from_str(xml).map_err(|e| EpubError::InvalidXml {
+            path: CONTAINER_PATH.to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    // 读取 META_INF/container.xml 文件,获取 opf 文件的路径
+    pub fn read_root_file(resources: &Resources) -> Result<String> {
+        let container_xml_content =
+            resources
+                .read_to_string(CONTAINER_PATH)
+                .map_err(|e| match e {
+                    EpubError::MissingResource { path } => EpubError::MissingContainer { path },
+                    e => e,
+                })?;
+
+        let container_content = Container::parse(&container_xml_content)?;
+
+        container_content
+            .rootfiles
+            .rootfile
+            .iter()
+            .find(|rootfile| rootfile.full_path.ends_with(".opf"))
+            .map(|rootfile| rootfile.full_path.clone())
+            .ok_or(EpubError::MissingRootfile {
+                path: CONTAINER_PATH.to_string(),
+            })
+    }
+}
@@ -0,0 +1,83 @@
+use std::{fmt, io};
+
+// Failures while opening and parsing an EPUB, each carries the offending path
+#[derive(Debug)]
+pub enum EpubError {
+    // 文件不是 zip 压缩包
+    NotZip { path: String, message: String },
+    // 缺少 META-INF/container.xml
+    MissingContainer { path: String },
+    // container.xml 中没有 opf 文件
+    MissingRootfile { path: String },
+    // opf 文件不是合法的 XML
+    InvalidOpf { path: String, message: String },
+    // container.xml 或 NCX 不是合法的 XML
+    InvalidXml { path: String, message: String },
+    // 既没有导航文档也没有 NCX, 并且 spine 为空
+    MissingToc { path: String },
+    // manifest / spine 引用的文件不在压缩包中
+    MissingResource { path: String },
+    // 无法解析或者在书中找不到的 CFI
+    InvalidCfi { cfi: String },
+    Io { path: String, source: io::Error },
+}
+
+pub type Result<T> = std::result::Result<T, EpubError>;
+
+impl EpubError {
+    pub fn io(path: &str, source: io::Error) -> Self {
+        if source.kind() == io::ErrorKind::NotFound {
+            EpubError::MissingResource {
+                path: path.to_string(),
+            }
+        } else {
+            EpubError::Io {
+                path: path.to_string(),
+                source,
+            }
+        }
+    }
+}
+
+impl fmt::Display for EpubError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EpubError::NotZip { path, message } => {
+                write!(f, "{} is not a valid EPUB (zip) archive: {}", path, message)
+            }
+            EpubError::MissingContainer { path } => {
+                write!(f, "{} is missing, the EPUB has no container", path)
+            }
+            EpubError::MissingRootfile { path } => {
+                write!(f, "{} does not declare an OPF rootfile", path)
+            }
+            EpubError::InvalidOpf { path, message } => {
+                write!(f, "failed to parse the OPF package {}: {}", path, message)
+            }
+            EpubError::InvalidXml { path, message } => {
+                write!(f, "failed to parse {}: {}", path, message)
+            }
+            EpubError::MissingToc { path } => {
+                write!(f, "no navigation document, NCX or spine found: {}", path)
+            }
+            EpubError::MissingResource { path } => {
+                write!(
+                    f,
+                    "{} is referenced by the book but missing from the archive",
+                    path
+                )
+            }
+            EpubError::InvalidCfi { cfi } => write!(f, "{} is not a valid CFI for this book", cfi),
+            EpubError::Io { path, source } => write!(f, "failed to read {}: {}", path, source),
+        }
+    }
+}
+
+impl std::error::Error for EpubError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EpubError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
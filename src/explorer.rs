@@ -1 +1,23 @@
+pub mod bookmark;
+pub mod highlight;
 pub mod index;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// 缓存目录中的 JSON 文件, 不存在或损坏时返回 None
+pub fn load_json<T: DeserializeOwned>(dir: &Path, name: &str) -> Option<T> {
+    let content = fs::read_to_string(dir.join(name)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+// 先写临时文件再替换, 中途退出也不会损坏原来的文件
+pub fn save_json_atomic<T: Serialize>(value: &T, dir: &Path, name: &str) -> io::Result<()> {
+    let content = serde_json::to_string(value)?;
+    let temp_path = dir.join(format!("{}.tmp", name));
+    fs::write(&temp_path, content)?;
+    fs::rename(temp_path, dir.join(name))
+}
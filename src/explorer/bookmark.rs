@@ -0,0 +1,75 @@
+use super::{load_json, save_json_atomic};
+use crate::ui::app::ReadingMode;
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 书签和 .dtoc 保存在同一个缓存目录中
+pub const BOOKMARK_FILE_NAME: &str = ".bookmarks";
+
+// 书签记录所在的文件和大纲锚点, 而不是大纲的序号, 重新生成索引后仍然可以找到
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    // 章节标题
+    pub chapter: String,
+    // 章节所在的文件和大纲锚点, 连续阅读模式下锚点为空
+    pub path: String,
+    pub anchor: String,
+    pub mode: ReadingMode,
+    // 章节文本中的字节位置
+    pub offset: usize,
+    // 书签位置的 EPUB CFI, 与阅读模式和大纲无关, 旧的书签为空
+    #[serde(default)]
+    pub cfi: String,
+    // 书签位置开头的文字, 章节内容变化后用它重新定位
+    pub snippet: String,
+    // 创建时间, unix 秒
+    pub created: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Bookmarks {
+    pub items: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    // 没有书签或文件损坏时返回空列表
+    pub fn load(dir: &Path) -> Bookmarks {
+        load_json(dir, BOOKMARK_FILE_NAME).unwrap_or_default()
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        save_json_atomic(self, dir, BOOKMARK_FILE_NAME)
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// Find the snippet in the text, the occurrence closest to the saved offset wins. Falls back to
+// the saved offset when the text changed too much.
+pub fn locate(text: &str, snippet: &str, offset: usize) -> usize {
+    if snippet.is_empty() {
+        return floor_char_boundary(text, offset);
+    }
+
+    text.match_indices(snippet)
+        .map(|(index, _)| index)
+        .min_by_key(|index| index.abs_diff(offset))
+        .unwrap_or_else(|| floor_char_boundary(text, offset))
+}
+
+pub fn floor_char_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
@@ -0,0 +1,129 @@
+use super::{load_json, save_json_atomic};
+use crate::ui::app::ReadingMode;
+use epub_parser::book::Book;
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+pub const HIGHLIGHT_FILE_NAME: &str = ".highlights";
+
+// 与书签一样按文件和大纲锚点记录位置, 用高亮的文字重新定位
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Highlight {
+    pub text: String,
+    // 可以为空
+    pub note: String,
+    pub chapter: String,
+    pub path: String,
+    pub anchor: String,
+    pub mode: ReadingMode,
+    // 章节文本中的字节位置
+    pub offset: usize,
+    // 高亮开始位置的 EPUB CFI, 旧的高亮为空
+    #[serde(default)]
+    pub cfi: String,
+    // 创建时间, unix 秒
+    pub created: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Highlights {
+    pub items: Vec<Highlight>,
+}
+
+impl Highlights {
+    pub fn load(dir: &Path) -> Highlights {
+        load_json(dir, HIGHLIGHT_FILE_NAME).unwrap_or_default()
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        save_json_atomic(self, dir, HIGHLIGHT_FILE_NAME)
+    }
+
+    // Highlights in reading order: spine order of their files, then position in the chapter
+    pub fn sorted(&self, book: &Book) -> Vec<Highlight> {
+        let mut items = self.items.clone();
+        items.sort_by_key(|highlight| {
+            let spine_index = book
+                .spine()
+                .iter()
+                .position(|item| item.href == highlight.path)
+                .unwrap_or(usize::MAX);
+            (spine_index, highlight.offset)
+        });
+        items
+    }
+
+    // Markdown with the book metadata, highlights are grouped by chapter
+    pub fn to_markdown(&self, book: &Book) -> String {
+        let metadata = book.metadata();
+        let mut out = format!("# {}\n\n", book.title());
+
+        let fields = [
+            ("作者", metadata.author()),
+            ("出版社", metadata.publisher.clone()),
+            ("出版日期", metadata.date.clone()),
+            ("语言", metadata.language.clone()),
+            (
+                "标识符",
+                metadata
+                    .identifiers
+                    .iter()
+                    .map(|identifier| identifier.value.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        ];
+        for (label, value) in fields {
+            if !value.is_empty() {
+                out.push_str(&format!("- {}: {}\n", label, value));
+            }
+        }
+
+        let mut chapter = None;
+        for highlight in self.sorted(book) {
+            if chapter != Some(highlight.chapter.clone()) {
+                out.push_str(&format!("\n## {}\n", highlight.chapter));
+                chapter = Some(highlight.chapter.clone());
+            }
+
+            out.push('\n');
+            for line in highlight.text.lines() {
+                match line.trim() {
+                    "" => out.push_str(">\n"),
+                    line => out.push_str(&format!("> {}\n", line)),
+                }
+            }
+            if !highlight.note.is_empty() {
+                out.push_str(&format!("\n{}\n", highlight.note));
+            }
+        }
+
+        out
+    }
+
+    pub fn to_json(&self, book: &Book) -> serde_json::Result<String> {
+        let metadata = book.metadata();
+
+        serde_json::to_string_pretty(&serde_json::json!({
+            "title": book.title(),
+            "author": metadata.author(),
+            "publisher": metadata.publisher,
+            "date": metadata.date,
+            "language": metadata.language,
+            "identifiers": metadata.identifiers,
+            "highlights": self
+                .sorted(book)
+                .iter()
+                .map(|highlight| serde_json::json!({
+                    "chapter": highlight.chapter,
+                    "text": highlight.text,
+                    "note": highlight.note,
+                    "cfi": highlight.cfi,
+                    "created": highlight.created,
+                }))
+                .collect::<Vec<_>>(),
+        }))
+    }
+}
@@ -1,13 +1,83 @@
-use crate::book::Book;
+use super::{load_json, save_json_atomic};
+use crate::config::RubyMode;
+use crate::ui::app::ReadingMode;
+use epub_parser::book::{Book, FlatToc};
 
 use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+// Save the file corresponding to the chapter and anchor
+pub const TOC_FILE_NAME: &str = ".dtoc";
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Explorer {
     // 当前阅读的章节,户退出的时候进行保存
     pub selected: usize,
-    // 当前阅读的内容位置, 用户退出的时候进行保存
+    // 阅读模式和连续阅读时的 spine 文档
+    #[serde(default)]
+    pub mode: ReadingMode,
+    #[serde(default)]
+    pub spine_index: usize,
+    // 当前阅读的内容位置(章节文本中的字节位置, 与终端宽度无关), 用户退出的时候进行保存
     pub position: usize,
+    // 阅读位置的 EPUB CFI, 章节内容变化后优先使用它定位
+    #[serde(default)]
+    pub cfi: String,
+    // 这本书的注音显示方式, 用 R 切换后保存
+    #[serde(default)]
+    pub ruby: Option<RubyMode>,
     // 章节列表
     pub book: Book,
 }
+
+impl Explorer {
+    // 旧版本或损坏的索引文件返回 None
+    pub fn load(dir: &Path) -> Option<Explorer> {
+        load_json(dir, TOC_FILE_NAME)
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        save_json_atomic(self, dir, TOC_FILE_NAME)
+    }
+
+    // 按 CFI 选择阅读位置所在的大纲条目或 spine 文档, 书籍更新后序号可能已经改变.
+    // CFI 为空或无法解析时返回 false, 由调用者使用保存的序号
+    pub fn locate_cfi(&mut self) -> bool {
+        let Ok((index, _)) = self.book.resolve_cfi(&self.cfi) else {
+            return false;
+        };
+
+        match self.mode {
+            ReadingMode::Spine => self.spine_index = index,
+            ReadingMode::Toc => {
+                let book = &self.book;
+                let path = &book.spine()[index].href;
+                let contains = |toc: &FlatToc| {
+                    book.section_styled(&toc.path, &toc.anchor.id, &toc.anchor.end_id)
+                        .ok()
+                        .and_then(|text| book.cfi_offset_in(&self.cfi, index, &text))
+                        .is_some()
+                };
+                let entries = || {
+                    book.flat_toc()
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, toc)| &toc.path == path)
+                };
+
+                // 优先保留当前条目, 否则使用包含 CFI 的小节, 最后使用文件的第一个条目
+                let Some((selected, _)) = entries()
+                    .find(|(i, toc)| *i == self.selected && contains(toc))
+                    .or_else(|| entries().find(|(_, toc)| contains(toc)))
+                    .or_else(|| entries().next())
+                else {
+                    return false;
+                };
+                self.selected = selected;
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,32 @@
+use crate::config::Language;
+use std::time::SystemTime;
+
+// 文件大小, 例如 1.5 MB
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// 距离现在的时间, 例如 3 天前
+pub fn format_age(now: SystemTime, time: SystemTime, lang: Language) -> String {
+    let seconds = now.duration_since(time).unwrap_or_default().as_secs();
+
+    match seconds {
+        0..=59 => lang.text("刚刚", "just now").to_string(),
+        60..=3599 => format!("{} {}", seconds / 60, lang.text("分钟前", "min ago")),
+        3600..=86399 => format!("{} {}", seconds / 3600, lang.text("小时前", "h ago")),
+        _ => format!("{} {}", seconds / 86400, lang.text("天前", "days ago")),
+    }
+}
@@ -0,0 +1,36 @@
+use scraper::{ElementRef, Html};
+
+// Ids of all elements of a content document, in document order
+pub fn element_ids(html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+
+    document
+        .root_element()
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .filter_map(|element| element.value().id())
+        .map(str::to_string)
+        .collect()
+}
+
+// 注释所在的块元素
+const NOTE_BLOCKS: [&str; 7] = ["aside", "li", "p", "dd", "div", "section", "td"];
+
+// The content of a footnote or endnote. The id is usually on the note itself, but can also be
+// on the back link or an empty anchor inside it, then the closest block around it is used.
+pub fn note_html(html: &str, id: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let element = document
+        .root_element()
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .find(|element| element.value().id() == Some(id))?;
+
+    let note = std::iter::once(element)
+        .chain(element.ancestors().filter_map(ElementRef::wrap))
+        .find(|element| NOTE_BLOCKS.contains(&element.value().name()))
+        .unwrap_or(element);
+
+    // 只取内容, 否则脚注的 aside 会被当作隐藏的脚注
+    Some(note.inner_html())
+}
@@ -0,0 +1,32 @@
+//! EPUB parsing library used by the terminal reader.
+//!
+//! ```no_run
+//! let book = epub_parser::Book::open("book.epub")?;
+//! println!("{} - {}", book.metadata().title, book.metadata().author());
+//! for (index, chapter) in book.flat_toc().iter().enumerate() {
+//!     println!("{}: {}", chapter.title, book.chapter_text(index)?.len());
+//! }
+//! # Ok::<(), epub_parser::EpubError>(())
+//! ```
+
+pub mod book;
+pub(crate) mod cache;
+pub mod cfi;
+pub(crate) mod container;
+pub mod error;
+pub(crate) mod fragment;
+pub(crate) mod nav;
+pub mod opf;
+pub mod resource;
+pub mod search;
+pub mod styled;
+pub(crate) mod toc;
+
+pub use book::{Anchor, Book, FlatToc, LinkTarget, Toc};
+pub use cache::{content_hash, extract};
+pub use cfi::Cfi;
+pub use error::{EpubError, Result};
+pub use opf::{Item, OpfMetaData, SpineItems};
+pub use resource::{DirReader, ResourceReader, Resources, ZipReader};
+pub use search::SearchHit;
+pub use styled::{LineKind, Link, StyledLine, StyledSpan, StyledText, TextStyle};
@@ -1,208 +1,208 @@
-use container::Container;
-use dirs_next::home_dir;
-use quick_xml::de::from_str;
-use quick_xml::{self, Reader};
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use epub_parser::{Book, DirReader, EpubError, Resources, Result, ZipReader};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::{env, fs};
 
-mod book;
-mod cache;
-mod container;
+mod command;
+mod config;
 mod explorer;
-mod opf;
-mod toc;
+mod format;
 mod ui;
 
-use book::Book;
-use explorer::index::Explorer;
-use opf::Opf;
-use toc::Ncx;
+use config::Config;
+use explorer::index::{Explorer, TOC_FILE_NAME};
+use ui::app::ReadingMode;
 
 const EPUB_MIME_TYPE: &str = "application/epub+zip";
-const TEMP_DIR_NAME: &str = ".epub_reader_temp";
-// Save the file corresponding to the chapter and anchor
-const TOC_FILE_NAME: &str = ".dtoc";
-
-fn parse_container_xml(xml: &str) -> Container {
-    let mut reader = Reader::from_str(xml);
-    reader.trim_text(true);
-    from_str(xml).expect("Failed to parse container.xml")
-}
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().collect();
+    let config_path = take_option(&mut args, "--config");
 
     if args.len() < 2 {
-        println!("Usage: {} <path-to-epub-file>", args[0]);
-        return Ok(());
+        println!("Usage: {} <path-to-epub-file> [reindex] [extract]", args[0]);
+        println!("       {} clean [path-to-epub-file | cache-key]", args[0]);
+        println!("       {} cache list", args[0]);
+        println!("       {} cache prune --older-than <30d|12h|2w>", args[0]);
+        println!(
+            "       {} export <path-to-epub-file> [markdown|json]",
+            args[0]
+        );
+        println!();
+        println!("Options: --config <path>  默认读取 {}", config_path_hint());
+        return ExitCode::SUCCESS;
+    }
+
+    let config = match Config::load(config_path.as_deref().map(Path::new)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("无法读取配置文件 {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match args[1].as_str() {
+        "clean" => return command::clean(&config, args.get(2).map(String::as_str)),
+        "cache" => return command::cache(&config, &args[2..]),
+        "export" => return command::export(&config, &args[2..]),
+        _ => {}
     }
+
     let epub_path = &args[1];
     if !PathBuf::from(epub_path).exists() {
         println!("File not found: {}", epub_path);
-        return Ok(());
+        return ExitCode::FAILURE;
     }
 
-    let is_reindex = args.len() > 2 && args[2] == "reindex";
-    let releate_path = epub_path.split("/").last().unwrap();
-
-    let dir = create_temp_dir(releate_path, is_reindex).expect("Failed to create temp directory");
-    let mut book = parse_epub_structure(epub_path, dir)?;
+    let is_reindex = args.iter().skip(2).any(|arg| arg == "reindex");
+    // 解压到缓存目录后再阅读, 默认直接读取压缩包
+    let is_extract = args.iter().skip(2).any(|arg| arg == "extract");
+    // 缓存目录按文件内容区分, 重命名或移动后的书籍仍然使用原来的缓存
+    // 缓存目录只用来保存索引, 创建失败时(例如只读的 home 目录)仍然可以阅读
+    let dir = epub_parser::content_hash(epub_path)
+        .ok()
+        .and_then(|key| create_temp_dir(&config, &key).ok());
+    let source = fs::canonicalize(epub_path).unwrap_or_else(|_| PathBuf::from(epub_path));
+
+    let explorer = match open_resources(epub_path, dir.as_deref(), is_extract, is_reindex)
+        .and_then(|resources| parse_epub_structure(resources, source, dir.clone(), is_reindex))
+    {
+        Ok(explorer) => explorer,
+        Err(e) => {
+            eprintln!("无法打开 {}: {}", epub_path, e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let _ = ui::show::start(&mut book);
+    if let Err(e) = ui::show::start(&explorer, dir.unwrap_or_default(), config) {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
 
-    Ok(())
+    ExitCode::SUCCESS
 }
 
-fn parse_epub_structure(book_path: &str, dir: (PathBuf, bool)) -> io::Result<Book> {
-    let (dest_dir, is_cache) = dir;
+fn open_resources(
+    book_path: &str,
+    dest_dir: Option<&Path>,
+    is_extract: bool,
+    is_reindex: bool,
+) -> Result<Resources> {
+    if let Some(dest_dir) = dest_dir {
+        let is_extracted = dest_dir.join("mimetype").exists();
+
+        if is_extract && (!is_extracted || is_reindex) {
+            epub_parser::extract(book_path, dest_dir)?;
+        }
 
-    if !is_cache {
-        cache::dir(book_path, &dest_dir)?;
+        // 已经解压过的书籍继续从缓存目录读取
+        if dest_dir.join("mimetype").exists() {
+            return Ok(Resources::new(DirReader::new(dest_dir.to_path_buf())));
+        }
     }
 
+    Ok(Resources::new(ZipReader::open(book_path)?))
+}
+
+fn parse_epub_structure(
+    resources: Resources,
+    source: PathBuf,
+    dest_dir: Option<PathBuf>,
+    is_reindex: bool,
+) -> Result<Explorer> {
     println!("开始读取文件...");
-    let mut book = Book::default();
-
-    // 判断是否是文件夹， 读取本地缓存文件夹，文件夹的名称是书籍的名称
-    if dest_dir.is_dir() {
-        let mut opf: Opf = Opf::default();
-        let mut ncx: Ncx = Ncx::default();
-        // 遍历文件夹，读取文件 // mimitype, META_INF, OEBPS
-        for entry in fs::read_dir(dest_dir.clone()).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-
-            // 读取 mimetype 文件，判断是否是epub文件
-            if path.is_file() && path.ends_with("mimetype") {
-                let mut file = File::open(&path).unwrap();
-                let mut content = String::new();
-
-                file.read_to_string(&mut content).unwrap();
-
-                if content != EPUB_MIME_TYPE {
-                    println!("mimetype 文件不是 epub 文件,解析可能会出错");
-                } else {
-                    println!("mimetype 文件读取成功: {}", content);
-
-                    let root_file = read_meta_inf(&path);
-
-                    // 判断是否存在 root_file 文件
-                    let opf_file_path = path.parent().unwrap().join(root_file);
-
-                    if opf_file_path.exists() {
-                        // 读取 opf 文件
-                        opf = Opf::read_text(&opf_file_path);
-                    }
-
-                    let ncx_file_path = opf_file_path.parent().unwrap().join("toc.ncx");
-
-                    if ncx_file_path.exists() {
-                        // 生成大纲
-                        ncx = Ncx::read_text(&ncx_file_path);
-                        // println!("ncx: {:#?}", ncx);
-                    }
-                }
-            }
+
+    // 读取 mimetype 文件，判断是否是epub文件
+    let mimetype = resources.read_to_string("mimetype").unwrap_or_default();
+
+    if mimetype.trim() != EPUB_MIME_TYPE {
+        println!("mimetype 文件不是 epub 文件,解析可能会出错");
+    } else {
+        println!("mimetype 文件读取成功: {}", mimetype);
+    }
+
+    let Some(dest_dir) = dest_dir else {
+        let mut book = Book::from_resources(resources)?;
+        book.set_source(source);
+        return Ok(Explorer {
+            book,
+            ..Default::default()
+        });
+    };
+
+    // 旧版本或损坏的索引文件直接重新生成
+    let saved = Explorer::load(&dest_dir);
+
+    // TODO: if file exist, prompt update index? 30 day
+    if !is_reindex {
+        if let Some(mut explorer) = saved {
+            explorer.book.set_resources(resources);
+            explorer.book.set_source(source);
+            explorer.locate_cfi();
+            return Ok(explorer);
         }
+    }
 
-        let metadata = opf.package.metadata;
-
-        let toc = ncx
-            .nav_map
-            .nav_point
-            .iter()
-            .map(|nav_point| nav_point.get_toc())
-            .collect();
-
-        book = Book {
-            path: dest_dir.clone(),
-            title: metadata.title,
-            toc,
-            language: metadata.language,
-            date: metadata.date,
-            author: metadata.creator,
-            selected: 1,
-            flat_toc: vec![],
-            context: "empty".to_string(),
-        };
-
-        //  写入文件到TOC_FILE_NAME
-        let toc_file_path = dest_dir.join(TOC_FILE_NAME);
-
-        // TODO: if file exist, prompt update index? 30 day
-        if !toc_file_path.exists() {
-            book.generate_anchor_positions()?;
-            book.flatten_toc();
-
-            let explorer = Explorer {
-                book: book.clone(),
-                position: 0,
-                selected: 0,
-            };
-            let mut toc_file = File::create(&toc_file_path)?;
-            let toc_file_content = serde_json::to_string(&explorer)?;
-            toc_file.write_all(toc_file_content.as_bytes())?;
-            Ok(book)
+    let mut book = Book::from_resources(resources)?;
+    book.set_source(source);
+
+    let mut explorer = Explorer {
+        book,
+        ..Default::default()
+    };
+
+    // 重新生成索引时保留上次的阅读位置, 先按 CFI 定位, 再使用仍然有效的序号
+    if let Some(saved) = saved {
+        explorer.ruby = saved.ruby;
+        explorer.mode = saved.mode;
+        explorer.cfi = saved.cfi;
+        if explorer.locate_cfi() {
+            explorer.position = saved.position;
+        } else if saved.selected < explorer.book.flat_toc().len()
+            && saved.spine_index < explorer.book.spine().len().max(1)
+        {
+            explorer.selected = saved.selected;
+            explorer.spine_index = saved.spine_index;
+            explorer.position = saved.position;
         } else {
-            let mut book = File::open(&toc_file_path)?;
-            let mut toc_file_content = String::new();
-            book.read_to_string(&mut toc_file_content)?;
-            let book_json: Explorer = serde_json::from_str(&toc_file_content)?;
-            Ok(book_json.book)
+            explorer.mode = ReadingMode::default();
+            explorer.cfi.clear();
         }
-    } else {
-        Ok(book)
     }
-}
-
-fn create_temp_dir(file_name: &str, is_reindex: bool) -> Result<(PathBuf, bool), std::io::Error> {
-    let mut is_exist = false;
 
-    let mut temp_dir = home_dir().ok_or(std::io::Error::new(
-        std::io::ErrorKind::NotFound,
-        "Home directory not found",
-    ))?;
+    explorer
+        .save(&dest_dir)
+        .map_err(|e| EpubError::io(&dest_dir.join(TOC_FILE_NAME).to_string_lossy(), e))?;
+    Ok(explorer)
+}
 
-    temp_dir.push(TEMP_DIR_NAME);
+fn create_temp_dir(config: &Config, key: &str) -> std::io::Result<PathBuf> {
+    let temp_dir = config.cache_root()?.join(key);
 
     if !temp_dir.exists() {
-        println!("enter create_dir_all");
         fs::create_dir_all(&temp_dir)?;
     }
 
-    temp_dir.push(file_name);
+    Ok(temp_dir)
+}
 
-    if !temp_dir.exists() || is_reindex {
-        fs::create_dir_all(&temp_dir)?;
-    } else {
-        is_exist = true;
+// Remove `--config <path>` or `--config=<path>` from the arguments and return the path
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args
+        .iter()
+        .position(|arg| arg == name || arg.starts_with(&format!("{}=", name)))?;
+    let arg = args.remove(index);
+
+    match arg.split_once('=') {
+        Some((_, value)) => Some(value.to_string()),
+        None if index < args.len() => Some(args.remove(index)),
+        // 缺少路径时读取配置文件会失败并提示
+        None => Some(String::new()),
     }
-
-    Ok((temp_dir, is_exist))
 }
 
-fn read_meta_inf(path: &PathBuf) -> String {
-    // 读取 META_INF/container.xml 文件,获取 OEBPS 文件夹的路径
-    let container_xml_path = path.parent().unwrap().join("META-INF/container.xml");
-
-    let mut container_xml_file = File::open(&container_xml_path).unwrap();
-
-    let mut container_xml_content = String::new();
-
-    container_xml_file
-        .read_to_string(&mut container_xml_content)
-        .unwrap();
-
-    let container_content = parse_container_xml(&container_xml_content);
-
-    container_content
-        .rootfiles
-        .rootfile
-        .iter()
-        .find(|rootfile| rootfile.full_path.ends_with(".opf"))
-        .expect("Failed to find rootfile, read opf process failed")
-        .full_path
-        .clone()
+fn config_path_hint() -> String {
+    config::default_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| "(没有配置目录)".to_string())
 }
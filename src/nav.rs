@@ -0,0 +1,101 @@
+use crate::book::{Anchor, Toc};
+use crate::opf::resolve_href;
+use scraper::{ElementRef, Html};
+
+// EPUB 3 navigation document (nav.xhtml)
+#[derive(Debug, Default)]
+pub struct Nav {
+    pub toc: Vec<Toc>,
+}
+
+impl Nav {
+    // `href` is the manifest href of the nav document, links inside it are relative to it
+    pub fn parse(xml_text: &str, href: &str) -> Self {
+        let document = Html::parse_document(xml_text);
+
+        let navs: Vec<ElementRef> = document
+            .root_element()
+            .descendants()
+            .filter_map(ElementRef::wrap)
+            .filter(|element| element.value().name() == "nav")
+            .collect();
+
+        // 优先使用 epub:type="toc" 的 nav, 否则退回到第一个 nav
+        let nav = navs
+            .iter()
+            .find(|nav| is_toc_nav(nav))
+            .or_else(|| navs.first());
+
+        let toc = match nav.and_then(|nav| child_element(nav, "ol")) {
+            Some(ol) => build_toc_list(&ol, href),
+            None => vec![],
+        };
+
+        Self { toc }
+    }
+}
+
+fn is_toc_nav(nav: &ElementRef) -> bool {
+    nav.value()
+        .attr("epub:type")
+        .map(|types| types.split_whitespace().any(|t| t == "toc"))
+        .unwrap_or(false)
+}
+
+fn child_element<'a>(element: &ElementRef<'a>, name: &str) -> Option<ElementRef<'a>> {
+    element
+        .children()
+        .filter_map(ElementRef::wrap)
+        .find(|child| child.value().name() == name)
+}
+
+fn build_toc_list(ol: &ElementRef, href: &str) -> Vec<Toc> {
+    ol.children()
+        .filter_map(ElementRef::wrap)
+        .filter(|li| li.value().name() == "li")
+        .filter_map(|li| build_toc_item(&li, href))
+        .collect()
+}
+
+fn build_toc_item(li: &ElementRef, href: &str) -> Option<Toc> {
+    let children = match child_element(li, "ol") {
+        Some(ol) => build_toc_list(&ol, href),
+        None => vec![],
+    };
+
+    // <li> 的标题是 <a>, 没有链接的分组标题是 <span>
+    let label = child_element(li, "a").or_else(|| child_element(li, "span"));
+
+    let title = label
+        .map(|label| label.text().collect::<Vec<_>>().join(" "))
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let link = label
+        .and_then(|label| label.value().attr("href"))
+        .map(|link| resolve_href(href, link));
+
+    let (path, id) = match link {
+        Some(link) => match link.find('#') {
+            Some(index) => (link[..index].to_string(), link[index + 1..].to_string()),
+            None => (link, "".to_string()),
+        },
+        // 分组标题没有自己的文件, 使用第一个子节点的位置
+        None => match children.first() {
+            Some(first) => (first.path.clone(), first.anchor.id.clone()),
+            None => return None,
+        },
+    };
+
+    Some(Toc {
+        title,
+        path,
+        anchor: Anchor {
+            id,
+            end_id: String::new(),
+        },
+        children,
+    })
+}
@@ -1,16 +1,19 @@
 use std::fs::File;
 use std::io::{self, BufReader, Read, Seek};
-use std::path::PathBuf;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use quick_xml::de::from_str;
-use quick_xml::{self, Reader};
+use quick_xml::de::{from_str, DeError};
+use quick_xml::events::Event;
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::{self, NsReader, Reader};
 
 #[derive(Debug, Default)]
 pub struct Opf {
     pub package: Package,
     pub spine_items: Vec<SpineItems>,
+    // CFI 的第一步, 见 spine_step
+    pub spine_step: usize,
 }
 
 #[derive(Debug, PartialEq, Default, Deserialize)]
@@ -18,48 +21,335 @@ pub struct Opf {
 pub struct Package {
     #[serde(rename = "@version")]
     pub version: String,
-    #[serde(rename = "metadata")]
+    // metadata 需要按命名空间解析, 见 OpfMetaData::parse
+    #[serde(skip)]
     pub metadata: OpfMetaData,
     #[serde(rename = "manifest")]
     pub manifest: Manifest,
     #[serde(rename = "spine")]
     pub spine: Spine,
+    // EPUB 2 的 guide, EPUB 3 中已经废弃但仍然常见
+    #[serde(rename = "guide")]
+    pub guide: Guide,
     #[serde(rename = "@xmlns")]
     xmlns: String,
 }
 
-#[derive(Debug, PartialEq, Default, Deserialize)]
+const DC_NAMESPACE: &[u8] = b"http://purl.org/dc/elements/1.1/";
+
+// <metadata>, <manifest>, <spine>: spine 通常是 package 的第三个子元素
+pub const DEFAULT_SPINE_STEP: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct OpfMetaData {
-    #[serde(rename = "@xmlns:dc")]
-    pub xmlns_dc: String,
-    #[serde(rename = "@xmlns:opf")]
-    pub xmlns_opf: String,
-    // #[serde(rename = "dc:title")]
     pub title: String,
-    // #[serde(rename = "dc:language")]
     pub language: String,
-    // #[serde(rename = "dc:creator")]
-    pub creator: String,
-    // #[serde(rename = "dc:publisher")]
+    pub creators: Vec<Contributor>,
+    pub contributors: Vec<Contributor>,
+    pub identifiers: Vec<Identifier>,
+    pub subjects: Vec<String>,
+    pub description: String,
     pub publisher: String,
-    // #[serde(rename = "dc:date")]
+    pub rights: String,
     pub date: String,
+    // EPUB 3 的 dcterms:modified
+    pub modified: String,
+    pub series: Option<Series>,
     pub meta: Vec<OpfMeta>,
 }
 
-#[derive(Debug, PartialEq, Default, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Contributor {
+    pub id: String,
+    pub name: String,
+    // MARC relator, 例如 aut, edt, ill, trl
+    pub role: String,
+    pub file_as: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Identifier {
+    pub id: String,
+    // ISBN, UUID, DOI ...
+    pub scheme: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Series {
+    pub name: String,
+    pub index: String,
+}
+
+// <meta>: EPUB 2 的 name/content 或 EPUB 3 的 property/refines
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct OpfMeta {
-    #[serde(rename = "@name")]
+    pub id: String,
     pub name: String,
-    #[serde(rename = "@content")]
     pub content: String,
+    pub property: String,
+    pub refines: String,
+    pub value: String,
+}
+
+// A metadata child element before refinements are applied
+#[derive(Debug, Default)]
+struct MetaElement {
+    is_dc: bool,
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+}
+
+impl MetaElement {
+    fn attr(&self, name: &str) -> String {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    }
+
+    fn contributor(&self) -> Contributor {
+        Contributor {
+            id: self.attr("id"),
+            name: self.text.clone(),
+            role: self.attr("role"),
+            file_as: self.attr("file-as"),
+        }
+    }
+}
+
+impl OpfMetaData {
+    // Parse <metadata> with namespace resolution, prefixes are not assumed to be `dc:`/`opf:`
+    pub fn parse(xml_text: &str) -> Self {
+        let elements = read_meta_elements(xml_text);
+        let mut metadata = OpfMetaData::default();
+
+        for element in elements.iter().filter(|element| element.is_dc) {
+            let text = element.text.clone();
+            match element.name.as_str() {
+                "title" if metadata.title.is_empty() => metadata.title = text,
+                "language" if metadata.language.is_empty() => metadata.language = text,
+                "creator" => metadata.creators.push(element.contributor()),
+                "contributor" => metadata.contributors.push(element.contributor()),
+                "identifier" => metadata.identifiers.push(Identifier {
+                    id: element.attr("id"),
+                    scheme: element.attr("scheme"),
+                    value: text,
+                }),
+                "subject" => metadata.subjects.push(text),
+                "description" => metadata.description = text,
+                "publisher" => metadata.publisher = text,
+                "rights" => metadata.rights = text,
+                "date" if metadata.date.is_empty() => metadata.date = text,
+                _ => {}
+            }
+        }
+
+        metadata.meta = elements
+            .iter()
+            .filter(|element| !element.is_dc && element.name == "meta")
+            .map(|element| OpfMeta {
+                id: element.attr("id"),
+                name: element.attr("name"),
+                content: element.attr("content"),
+                property: element.attr("property"),
+                refines: element.attr("refines"),
+                value: element.text.clone(),
+            })
+            .collect();
+
+        metadata.apply_meta();
+        metadata
+    }
+
+    // Apply EPUB 3 refinements and EPUB 2 calibre metas
+    fn apply_meta(&mut self) {
+        for meta in &self.meta {
+            let target = meta.refines.trim_start_matches('#');
+
+            match meta.property.as_str() {
+                "dcterms:modified" if target.is_empty() => self.modified = meta.value.clone(),
+                "belongs-to-collection" if target.is_empty() => {
+                    let index = self
+                        .meta
+                        .iter()
+                        .find(|m| {
+                            !meta.id.is_empty()
+                                && m.refines.trim_start_matches('#') == meta.id
+                                && m.property == "group-position"
+                        })
+                        .map(|m| m.value.clone())
+                        .unwrap_or_default();
+
+                    self.series = Some(Series {
+                        name: meta.value.clone(),
+                        index,
+                    });
+                }
+                "role" | "file-as" => {
+                    let contributor = self
+                        .creators
+                        .iter_mut()
+                        .chain(self.contributors.iter_mut())
+                        .find(|c| !c.id.is_empty() && c.id == target);
+
+                    if let Some(contributor) = contributor {
+                        match meta.property.as_str() {
+                            "role" => contributor.role = meta.value.clone(),
+                            _ => contributor.file_as = meta.value.clone(),
+                        }
+                    }
+                }
+                "identifier-type" => {
+                    let identifier = self
+                        .identifiers
+                        .iter_mut()
+                        .find(|i| !i.id.is_empty() && i.id == target);
+
+                    if let Some(identifier) = identifier {
+                        identifier.scheme = meta.value.clone();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // calibre 的系列信息, EPUB 3 的 belongs-to-collection 优先
+        if self.series.is_none() {
+            let name = self.meta_content("calibre:series");
+            if !name.is_empty() {
+                self.series = Some(Series {
+                    name,
+                    index: self.meta_content("calibre:series_index"),
+                });
+            }
+        }
+
+        // 没有声明 scheme 的标识符按 urn 前缀推断
+        for identifier in &mut self.identifiers {
+            if identifier.scheme.is_empty() {
+                let value = identifier.value.to_lowercase();
+                if value.starts_with("urn:uuid:") {
+                    identifier.scheme = "UUID".to_string();
+                } else if value.starts_with("urn:isbn:") {
+                    identifier.scheme = "ISBN".to_string();
+                }
+            }
+        }
+    }
+
+    // EPUB 2 <meta name="..." content="...">
+    pub fn meta_content(&self, name: &str) -> String {
+        self.meta
+            .iter()
+            .find(|meta| meta.name == name)
+            .map(|meta| meta.content.clone())
+            .unwrap_or_default()
+    }
+
+    // Creators without a role or with the `aut` role, joined for display
+    pub fn author(&self) -> String {
+        self.creators
+            .iter()
+            .filter(|creator| creator.role.is_empty() || creator.role == "aut")
+            .map(|creator| creator.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// Collect the Dublin Core elements and <meta> elements inside <metadata>
+fn read_meta_elements(xml_text: &str) -> Vec<MetaElement> {
+    let mut reader = NsReader::from_str(xml_text);
+    reader.trim_text(true);
+
+    let mut elements = vec![];
+    let mut in_metadata = false;
+    let mut current: Option<MetaElement> = None;
+
+    while let Ok((namespace, event)) = reader.read_resolved_event() {
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                let is_dc =
+                    matches!(namespace, ResolveResult::Bound(Namespace(ns)) if ns == DC_NAMESPACE);
+
+                if name == "metadata" {
+                    in_metadata = matches!(event, Event::Start(_));
+                    continue;
+                }
+
+                if !in_metadata || !(is_dc || name == "meta") {
+                    continue;
+                }
+
+                // opf:role 和 role 都按本地名称保存
+                let attributes = e
+                    .attributes()
+                    .flatten()
+                    .filter(|attr| attr.key.as_namespace_binding().is_none())
+                    .map(|attr| {
+                        let (_, local) = reader.resolve_attribute(attr.key);
+                        let key = String::from_utf8_lossy(local.as_ref()).to_string();
+                        let value = attr
+                            .unescape_value()
+                            .map(|value| value.to_string())
+                            .unwrap_or_default();
+                        (key, value)
+                    })
+                    .collect();
+
+                let element = MetaElement {
+                    is_dc,
+                    name,
+                    attributes,
+                    text: String::new(),
+                };
+
+                match event {
+                    Event::Empty(_) => elements.push(element),
+                    _ => current = Some(element),
+                }
+            }
+            Event::Text(e) => {
+                if let Some(element) = current.as_mut() {
+                    element.text.push_str(&e.unescape().unwrap_or_default());
+                }
+            }
+            Event::CData(e) => {
+                if let Some(element) = current.as_mut() {
+                    element.text.push_str(&String::from_utf8_lossy(&e));
+                }
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"metadata" {
+                    break;
+                }
+                if let Some(element) = current.take() {
+                    elements.push(element);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    elements
 }
 
 #[derive(Debug, PartialEq, Default, Deserialize)]
 #[serde(default)]
 pub struct Spine {
+    // EPUB 2 中指向 NCX 的 manifest id
+    #[serde(rename = "@toc")]
+    pub toc: String,
     pub itemref: Vec<ItemRef>,
 }
 
@@ -67,6 +357,25 @@ pub struct Spine {
 pub struct ItemRef {
     #[serde(rename = "@idref")]
     pub idref: String,
+    #[serde(rename = "@linear", default)]
+    pub linear: String,
+}
+
+#[derive(Debug, PartialEq, Default, Deserialize)]
+#[serde(default)]
+pub struct Guide {
+    pub reference: Vec<Reference>,
+}
+
+#[derive(Debug, PartialEq, Default, Deserialize)]
+pub struct Reference {
+    // cover, toc, text ...
+    #[serde(rename = "@type", default)]
+    pub kind: String,
+    #[serde(rename = "@href")]
+    pub href: String,
+    #[serde(rename = "@title", default)]
+    pub title: String,
 }
 
 #[derive(Debug, PartialEq, Default, Deserialize)]
@@ -74,7 +383,7 @@ pub struct Manifest {
     pub item: Vec<Item>,
 }
 
-#[derive(Debug, PartialEq, Default, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct Item {
     #[serde(rename = "@id")]
     pub id: String,
@@ -82,52 +391,102 @@ pub struct Item {
     pub href: String,
     #[serde(rename = "@media-type")]
     pub media_type: String,
+    #[serde(rename = "@properties", default)]
+    pub properties: String,
 }
 
-impl Opf {
-    pub fn read_text(path: &PathBuf) -> Self {
-        let mut file = File::open(path).unwrap();
-        let mut content = String::new();
-
-        file.read_to_string(&mut content)
-            .expect("Failed to read opf file");
+impl Item {
+    // 解码后的路径, 相对于 opf 文件所在的目录
+    pub fn path(&self) -> String {
+        resolve_href("", &self.href)
+    }
+}
 
-        let package = Package::parse(&content);
+impl Opf {
+    pub fn parse(xml_text: &str) -> Result<Self, DeError> {
+        let package = Package::parse(xml_text)?;
         let spine_items = Opf::get_spines(&package.spine, &package.manifest);
 
-        Self {
+        Ok(Self {
             package,
             spine_items,
-        }
+            spine_step: spine_step(xml_text),
+        })
     }
 
+    // Spine items in itemref order
     pub fn get_spines(spine: &Spine, manifest: &Manifest) -> Vec<SpineItems> {
-        let mut spine_items = vec![];
-
-        let spine_value = &spine
+        spine
             .itemref
             .iter()
-            .map(|itemref| {
-                let idref = &itemref.idref;
-                idref
+            .filter_map(|itemref| {
+                let item = manifest.item.iter().find(|item| item.id == itemref.idref)?;
+
+                Some(SpineItems {
+                    id: item.id.clone(),
+                    href: item.path(),
+                    media_type: item.media_type.clone(),
+                    linear: itemref.linear != "no",
+                })
             })
-            .collect::<Vec<&String>>();
-
-        manifest.item.iter().for_each(|item| {
-            let id = &item.id;
-            let href = &item.href;
-            let media_type = &item.media_type;
-
-            if spine_value.contains(&id) {
-                spine_items.push(SpineItems {
-                    id: id.clone(),
-                    href: href.clone(),
-                    media_type: media_type.clone(),
-                });
-            }
+            .collect()
+    }
+
+    // EPUB 3 导航文档: manifest 中 properties 含有 nav 的 item
+    pub fn nav_item(&self) -> Option<&Item> {
+        self.package
+            .manifest
+            .item
+            .iter()
+            .find(|item| item.properties.split_whitespace().any(|p| p == "nav"))
+    }
+
+    // NCX: spine 的 toc 属性指向的 item, 否则按 media-type 查找
+    pub fn ncx_item(&self) -> Option<&Item> {
+        let items = &self.package.manifest.item;
+
+        items
+            .iter()
+            .find(|item| !self.package.spine.toc.is_empty() && item.id == self.package.spine.toc)
+            .or_else(|| {
+                items
+                    .iter()
+                    .find(|item| item.media_type == "application/x-dtbncx+xml")
+            })
+    }
+
+    // The cover: the EPUB 3 cover-image item, the EPUB 2 <meta name="cover">, or the guide's
+    // cover reference. The guide usually points to a cover page rather than to the image.
+    pub fn cover_href(&self) -> Option<String> {
+        let items = &self.package.manifest.item;
+
+        let cover_image = items.iter().find(|item| {
+            item.properties
+                .split_whitespace()
+                .any(|p| p == "cover-image")
         });
+        if let Some(item) = cover_image {
+            return Some(item.path());
+        }
+
+        // content 应该是 item 的 id, 也有书籍直接写成 href
+        let content = self.package.metadata.meta_content("cover");
+        if !content.is_empty() {
+            let item = items
+                .iter()
+                .find(|item| item.id == content)
+                .or_else(|| items.iter().find(|item| item.href == content));
+            if let Some(item) = item {
+                return Some(item.path());
+            }
+        }
 
-        spine_items
+        self.package
+            .guide
+            .reference
+            .iter()
+            .find(|reference| reference.kind.eq_ignore_ascii_case("cover"))
+            .map(|reference| resolve_href("", reference.href.split('#').next().unwrap_or("")))
     }
 
     pub fn _load_more(file_path: &str, current_pos: &mut u64) -> io::Result<String> {
@@ -144,19 +503,104 @@ impl Opf {
 }
 
 impl Package {
-    pub fn parse(xml_text: &str) -> Self {
-        let new_xml = xml_text.replace("dc:", "dc_");
-        let mut reader = Reader::from_str(&new_xml);
+    pub fn parse(xml_text: &str) -> Result<Self, DeError> {
+        let mut reader = Reader::from_str(xml_text);
 
         reader.trim_text(true);
 
-        from_str(&new_xml).unwrap()
+        let mut package: Package = from_str(xml_text)?;
+        package.metadata = OpfMetaData::parse(xml_text);
+        Ok(package)
+    }
+}
+
+// The CFI step of <spine> among the child elements of <package>, 6 when it follows <metadata>
+// and <manifest> as usual
+pub fn spine_step(xml_text: &str) -> usize {
+    let mut reader = Reader::from_str(xml_text);
+    let mut depth = 0;
+    let mut children = 0;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                if depth == 1 {
+                    children += 1;
+                    if e.local_name().as_ref() == b"spine" {
+                        return children * 2;
+                    }
+                }
+                depth += 1;
+            }
+            Ok(Event::Empty(e)) if depth == 1 => {
+                children += 1;
+                if e.local_name().as_ref() == b"spine" {
+                    return children * 2;
+                }
+            }
+            Ok(Event::End(_)) => depth -= 1,
+            Ok(Event::Eof) | Err(_) => return DEFAULT_SPINE_STEP,
+            _ => {}
+        }
+    }
+}
+
+// Resolve `href` found in the document at `base_href`, both relative to the OPF directory
+pub fn resolve_href(base_href: &str, href: &str) -> String {
+    let href = percent_decode(href);
+    let mut parts: Vec<&str> = base_href.split('/').collect();
+    // 去掉文件名, 只保留目录部分
+    parts.pop();
+
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
     }
+
+    parts.join("/")
+}
+
+// Links with a scheme, e.g. http: or mailto:, point outside of the book
+pub fn is_external(href: &str) -> bool {
+    href.find(':').is_some_and(|index| {
+        index > 0
+            && href[..index]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SpineItems {
     pub id: String,
     pub href: String,
     pub media_type: String,
+    // linear="no" 的文档不参与连续阅读, 只能通过跳转打开
+    pub linear: bool,
 }
@@ -0,0 +1,114 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use zip::ZipArchive;
+
+use crate::cache::open_epub;
+use crate::error::{EpubError, Result};
+
+// Read access to the files of an EPUB, paths are relative to the archive root
+pub trait ResourceReader: Send + Sync {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    fn exists(&self, path: &str) -> bool;
+
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        let bytes = self.read(path)?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+// 直接从 epub 压缩包中按需读取
+pub struct ZipReader {
+    archive: Mutex<ZipArchive<BufReader<File>>>,
+}
+
+impl ZipReader {
+    pub fn open(book_path: &str) -> Result<Self> {
+        Ok(Self {
+            archive: Mutex::new(open_epub(book_path)?),
+        })
+    }
+}
+
+impl ResourceReader for ZipReader {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let mut archive = self.archive.lock().unwrap();
+        let mut file = archive
+            .by_name(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+
+        let mut bytes = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        let archive = self.archive.lock().unwrap();
+        let exists = archive.file_names().any(|name| name == path);
+        exists
+    }
+}
+
+// 读取解压到缓存目录中的文件
+pub struct DirReader {
+    root: PathBuf,
+}
+
+impl DirReader {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+impl ResourceReader for DirReader {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(path))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.root.join(path).is_file()
+    }
+}
+
+// Shared handle to the reader backend of a book
+#[derive(Clone)]
+pub struct Resources(Arc<dyn ResourceReader>);
+
+impl Resources {
+    pub fn new(reader: impl ResourceReader + 'static) -> Self {
+        Self(Arc::new(reader))
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>> {
+        self.0.read(path).map_err(|e| EpubError::io(path, e))
+    }
+
+    pub fn read_to_string(&self, path: &str) -> Result<String> {
+        self.0
+            .read_to_string(path)
+            .map_err(|e| EpubError::io(path, e))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.0.exists(path)
+    }
+}
+
+impl Default for Resources {
+    fn default() -> Self {
+        Resources::new(DirReader::new(PathBuf::new()))
+    }
+}
+
+impl fmt::Debug for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Resources")
+    }
+}
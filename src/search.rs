@@ -0,0 +1,180 @@
+use std::ops::Range;
+
+use crate::book::Book;
+use crate::error::Result;
+
+// 搜索结果前后保留的字符数
+const SNIPPET_CONTEXT: usize = 30;
+
+#[derive(Debug, Default, Clone)]
+pub struct SearchHit {
+    // flat_toc 的下标, search_spine 中是 spine 的下标
+    pub index: usize,
+    pub title: String,
+    // 命中所在的行, 相对于该章节的文本
+    pub line: usize,
+    pub snippet: String,
+}
+
+impl Book {
+    // Case-insensitive search in the rendered text of every TOC entry
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        // 指向同一段内容的大纲条目(例如分组标题)只搜索一次
+        let mut searched = vec![];
+        let entries = self.flat_toc.iter().enumerate().filter(move |(_, toc)| {
+            let range = (&toc.path, &toc.anchor.id, &toc.anchor.end_id);
+            let first = !searched.contains(&range);
+            searched.push(range);
+            first
+        });
+
+        search_texts(
+            query,
+            entries.map(|(index, toc)| (index, toc.title.clone(), self.chapter_text(index))),
+        )
+    }
+
+    // Case-insensitive search in every spine document, the hits are indexed by spine
+    pub fn search_spine(&self, query: &str) -> Vec<SearchHit> {
+        search_texts(
+            query,
+            (0..self.spine.len())
+                .map(|index| (index, self.spine_title(index), self.spine_text(index))),
+        )
+    }
+
+    // Title of a spine document: its TOC entry, or its href when it is not in the TOC
+    pub fn spine_title(&self, index: usize) -> String {
+        let Some(item) = self.spine.get(index) else {
+            return String::new();
+        };
+
+        self.flat_toc
+            .iter()
+            .find(|toc| toc.path == item.href)
+            .map(|toc| toc.title.clone())
+            .unwrap_or_else(|| item.href.clone())
+    }
+}
+
+// 章节按顺序读取, 查询为空时不读取任何章节
+fn search_texts(
+    query: &str,
+    texts: impl Iterator<Item = (usize, String, Result<String>)>,
+) -> Vec<SearchHit> {
+    let mut hits = vec![];
+
+    if query.trim().is_empty() {
+        return hits;
+    }
+
+    for (index, title, text) in texts {
+        // 读取失败的章节直接跳过
+        let Ok(text) = text else {
+            continue;
+        };
+
+        for (line_number, line) in text.lines().enumerate() {
+            for range in match_ranges(line, query) {
+                hits.push(SearchHit {
+                    index,
+                    title: title.clone(),
+                    line: line_number,
+                    snippet: snippet(line, range),
+                });
+            }
+        }
+    }
+
+    hits
+}
+
+// Byte ranges of the case-insensitive, non-overlapping matches of `query` in `text`
+pub fn match_ranges(text: &str, query: &str) -> Vec<Range<usize>> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    let mut ranges = vec![];
+
+    if query.is_empty() {
+        return ranges;
+    }
+
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut matched = 0;
+        let mut end = start;
+
+        while end < chars.len() && matched < query.len() {
+            let lower: Vec<char> = chars[end].1.to_lowercase().collect();
+            if query[matched..].starts_with(&lower) {
+                matched += lower.len();
+                end += 1;
+            } else {
+                break;
+            }
+        }
+
+        if matched == query.len() {
+            let end_byte = chars.get(end).map(|(i, _)| *i).unwrap_or(text.len());
+            ranges.push(chars[start].0..end_byte);
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+
+    ranges
+}
+
+fn snippet(line: &str, range: Range<usize>) -> String {
+    let before: Vec<char> = line[..range.start].chars().collect();
+    let after: Vec<char> = line[range.end..].chars().collect();
+
+    let before_start = before.len().saturating_sub(SNIPPET_CONTEXT);
+    let after_end = after.len().min(SNIPPET_CONTEXT);
+
+    format!(
+        "{}{}{}{}{}",
+        if before_start > 0 { "…" } else { "" },
+        before[before_start..]
+            .iter()
+            .collect::<String>()
+            .trim_start(),
+        &line[range],
+        after[..after_end].iter().collect::<String>().trim_end(),
+        if after_end < after.len() { "…" } else { "" },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_ranges_ignores_case() {
+        assert_eq!(match_ranges("Hello hELLO", "HeLLo"), vec![0..5, 6..11]);
+        assert_eq!(match_ranges("ΣΟΦΙΑ", "σοφια"), vec![0..10]);
+        // İ 转为小写后是两个字符, 整体匹配时范围仍然覆盖原来的字符
+        assert_eq!(match_ranges("İstanbul", "i\u{307}s"), vec![0..3]);
+        assert!(match_ranges("İstanbul", "is").is_empty());
+    }
+
+    #[test]
+    fn match_ranges_returns_byte_offsets() {
+        let text = "第一章 Intro 第一章";
+        let ranges = match_ranges(text, "第一章");
+
+        assert_eq!(ranges, vec![0..9, 16..25]);
+        assert!(ranges.iter().all(|range| &text[range.clone()] == "第一章"));
+        assert_eq!(match_ranges(text, "intro"), vec![10..15]);
+    }
+
+    #[test]
+    fn match_ranges_skips_overlapping_matches() {
+        assert_eq!(match_ranges("aaaa", "aa"), vec![0..2, 2..4]);
+        assert_eq!(match_ranges("aaa", "aa"), vec![0..2]);
+        assert_eq!(match_ranges("ababa", "aba"), vec![0..3]);
+        assert!(match_ranges("abc", "").is_empty());
+    }
+}
@@ -0,0 +1,856 @@
+use crate::cfi::{text_path, Step};
+use crate::opf::is_external;
+use ego_tree::{NodeId, NodeRef};
+use scraper::{ElementRef, Html, Node};
+use std::fmt;
+use std::ops::Range;
+
+// 无序列表每一层的符号
+const BULLETS: [&str; 3] = ["• ", "◦ ", "▪ "];
+const QUOTE_BAR: &str = "│ ";
+
+// Inline style of a piece of text, independent of the terminal library
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub code: bool,
+    pub link: bool,
+    // 标题级别 1-6, 0 表示不是标题
+    pub heading: u8,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StyledSpan {
+    pub text: String,
+    pub style: TextStyle,
+    // 所在的链接, StyledText::links 的下标
+    pub link: Option<usize>,
+}
+
+// A hyperlink of the chapter, its text is in the spans that refer to it
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Link {
+    // Book 读取章节时把书内的链接解析为相对于 opf 目录的路径, 保留 #fragment
+    pub href: String,
+    // epub:type="noteref", 指向脚注或尾注
+    pub is_note: bool,
+}
+
+impl Link {
+    pub fn is_external(&self) -> bool {
+        is_external(&self.href)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    #[default]
+    Text,
+    // 预格式化文本, 保留空白, 按字符折行
+    Pre,
+    // <hr>
+    Rule,
+    // 独占一行的图片, 文本是它的替代文字
+    Image,
+}
+
+// One line of a rendered chapter, before wrapping
+#[derive(Debug, Default, Clone)]
+pub struct StyledLine {
+    // 引用的竖线和列表的序号
+    pub prefix: String,
+    // 折行后续行的前缀, 与 prefix 等宽
+    pub indent: String,
+    pub spans: Vec<StyledSpan>,
+    pub kind: LineKind,
+    // LineKind::Image 的图片地址, Book 读取章节时解析为相对于 opf 目录的路径
+    pub image: String,
+    // <ruby> 的注音, 不在行的文本中
+    pub ruby: Vec<Ruby>,
+}
+
+// The annotation of a ruby base, e.g. the reading of a kanji
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Ruby {
+    // 注音对应的文字在 content() 中的字节范围
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl StyledLine {
+    // Text of the spans, without the prefix
+    pub fn content(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.spans.iter().all(|span| span.text.trim().is_empty())
+    }
+}
+
+// Where a piece of the rendered text comes from: a text node of the source document
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TextSource {
+    // 在纯文本中的字节位置
+    pub offset: usize,
+    // 文本节点的 CFI 路径, 从 <html> 开始
+    pub path: Vec<Step>,
+    // 这一段在文本节点中的起始位置(UTF-16), 节点中的换行会分成多段
+    pub start: usize,
+    // 原始的文本, 空白没有合并
+    pub text: String,
+}
+
+// A chapter rendered from XHTML into styled lines
+#[derive(Debug, Default, Clone)]
+pub struct StyledText {
+    pub lines: Vec<StyledLine>,
+    pub links: Vec<Link>,
+    pub sources: Vec<TextSource>,
+}
+
+impl StyledText {
+    // Unstyled text, one line per line of `text`
+    pub fn plain(text: &str) -> Self {
+        StyledText {
+            lines: text
+                .lines()
+                .map(|line| StyledLine {
+                    spans: vec![StyledSpan {
+                        text: line.to_string(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                })
+                .collect(),
+            links: vec![],
+            sources: vec![],
+        }
+    }
+
+    // Render an XHTML document or fragment
+    pub fn from_html(html: &str) -> Self {
+        Self::from_html_section(html, "", "").unwrap_or_default()
+    }
+
+    // Render the document from the element `start_id` up to, but not including, the element
+    // `end_id`. An empty `start_id` starts at the beginning of the body, an empty `end_id`
+    // runs to the end of the document. Returns None when `start_id` is not in the document.
+    pub fn from_html_section(html: &str, start_id: &str, end_id: &str) -> Option<Self> {
+        render_section(html, start_id, end_id, false).map(|(text, _)| text)
+    }
+}
+
+// Ids of the elements in the section `from_html_section` renders
+pub(crate) fn section_ids(html: &str, start_id: &str, end_id: &str) -> Option<Vec<String>> {
+    render_section(html, start_id, end_id, true).map(|(_, ids)| ids)
+}
+
+// 同时收集小节中的元素 id, 判断锚点属于哪个小节时与显示的内容一致
+fn render_section(
+    html: &str,
+    start_id: &str,
+    end_id: &str,
+    collect_ids: bool,
+) -> Option<(StyledText, Vec<String>)> {
+    let document = Html::parse_document(html);
+    let root = document.root_element();
+
+    let find = |id: &str| {
+        root.descendants()
+            .filter_map(ElementRef::wrap)
+            .find(|element| element.value().id() == Some(id))
+    };
+
+    let start = match start_id {
+        "" => None,
+        id => Some(find(id)?),
+    };
+    let end = match end_id {
+        "" => None,
+        id => find(id),
+    };
+
+    let body = root
+        .descendants()
+        .filter_map(ElementRef::wrap)
+        .find(|element| element.value().name() == "body")
+        .unwrap_or(root);
+
+    let mut renderer = Renderer {
+        start: start.map(|start| start.id()),
+        end: end.map(|end| end.id()),
+        end_ancestors: end
+            .map(|end| end.ancestors().map(|node| node.id()).collect())
+            .unwrap_or_default(),
+        inside: start.is_none(),
+        ids: collect_ids.then(Vec::new),
+        ..Default::default()
+    };
+    renderer.walk(body);
+    renderer.flush();
+
+    // 去掉末尾的空行
+    while renderer.lines.last().is_some_and(StyledLine::is_blank) {
+        renderer.lines.pop();
+    }
+
+    // 行号和行内位置转换为纯文本中的位置
+    let mut line_starts = Vec::with_capacity(renderer.lines.len());
+    let mut line_start = 0;
+    for line in &renderer.lines {
+        line_starts.push(line_start + line.prefix.len());
+        line_start += line.prefix.len() + line.content().len() + 1;
+    }
+
+    let sources = renderer
+        .sources
+        .into_iter()
+        .filter_map(|(line, column, mut source)| {
+            source.offset = line_starts.get(line)? + column;
+            Some(source)
+        })
+        .collect();
+
+    let text = StyledText {
+        lines: renderer.lines,
+        links: renderer.links,
+        sources,
+    };
+    Some((text, renderer.ids.unwrap_or_default()))
+}
+
+// 纯文本: 每一行是前缀加内容, 搜索和阅读位置都基于它
+impl fmt::Display for StyledText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, line) in self.lines.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            f.write_str(&line.prefix)?;
+            for span in &line.spans {
+                f.write_str(&span.text)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+enum Container {
+    Quote,
+    // 列表项, 序号只显示在第一行
+    Item { marker: String, used: bool },
+    // <dd>
+    Indent,
+}
+
+#[derive(Default)]
+struct Renderer {
+    lines: Vec<StyledLine>,
+    spans: Vec<StyledSpan>,
+    styles: Vec<TextStyle>,
+    containers: Vec<Container>,
+    // 每一层列表的下一个序号, 无序列表为 None
+    lists: Vec<Option<usize>>,
+    pre: usize,
+    links: Vec<Link>,
+    // 正在输出的链接
+    link: Option<usize>,
+    // 文本节点的来源, 以及它所在的行号和行内位置
+    sources: Vec<(usize, usize, TextSource)>,
+    // 当前行的注音
+    ruby: Vec<Ruby>,
+    // 只输出 start 到 end 之间的内容
+    start: Option<NodeId>,
+    end: Option<NodeId>,
+    // 结束锚点的祖先元素只有一部分在小节中
+    end_ancestors: Vec<NodeId>,
+    inside: bool,
+    done: bool,
+    // 小节中的元素 id, 只在需要时收集
+    ids: Option<Vec<String>>,
+    // 已经记录了所有子元素 id 的元素
+    collected: Option<NodeId>,
+}
+
+impl Renderer {
+    fn walk(&mut self, element: ElementRef) {
+        for child in element.children() {
+            if self.done {
+                return;
+            }
+
+            match child.value() {
+                Node::Text(text) if self.inside => self.push_node(child, text),
+                Node::Element(_) => {
+                    let Some(element) = ElementRef::wrap(child) else {
+                        continue;
+                    };
+
+                    if Some(child.id()) == self.end {
+                        self.done = true;
+                        return;
+                    }
+                    if Some(child.id()) == self.start {
+                        self.inside = true;
+                    }
+
+                    if self.inside {
+                        self.collect_ids(element);
+                        self.element(element);
+                        // 结束锚点在不显示的元素中, 例如脚注 <aside>, 小节在这个元素之后结束
+                        if self.end_ancestors.contains(&child.id()) {
+                            self.done = true;
+                            return;
+                        }
+                    } else {
+                        // 开始锚点之前的元素不输出, 只进入查找
+                        self.walk(element);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn collect_ids(&mut self, element: ElementRef) {
+        let Some(ids) = &mut self.ids else {
+            return;
+        };
+        if let Some(collected) = self.collected {
+            if element.ancestors().any(|node| node.id() == collected) {
+                return;
+            }
+        }
+
+        // 被结束锚点截断的元素只记录自己, 子元素在遍历时记录
+        let elements: Vec<ElementRef> = match self.end_ancestors.contains(&element.id()) {
+            true => vec![element],
+            false => {
+                self.collected = Some(element.id());
+                element.descendants().filter_map(ElementRef::wrap).collect()
+            }
+        };
+        ids.extend(
+            elements
+                .iter()
+                .filter_map(|element| element.value().id())
+                .map(str::to_string),
+        );
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+        let mut style = self.style();
+
+        match name {
+            "script" | "style" | "head" | "title" | "rp" | "rt" | "rtc" => {}
+            "ruby" => self.ruby(element),
+            "br" => self.line_break(),
+            "hr" => {
+                self.flush();
+                let (prefix, indent) = self.prefix(false);
+                self.lines.push(StyledLine {
+                    prefix,
+                    indent,
+                    spans: vec![],
+                    kind: LineKind::Rule,
+                    image: String::new(),
+                    ruby: vec![],
+                });
+            }
+            "img" => {
+                if let Some(src) = element.value().attr("src") {
+                    self.push_image(src, element.value().attr("alt"));
+                }
+            }
+            "svg" => {
+                // 只显示 svg 中引用的位图, 例如封面页的 <svg><image xlink:href="..."/></svg>
+                let image = element
+                    .descendants()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|child| child.value().name() == "image")
+                    .find_map(|child| {
+                        child
+                            .value()
+                            .attrs()
+                            .find(|(name, _)| *name == "href")
+                            .map(|(_, href)| href)
+                    });
+
+                if let Some(src) = image {
+                    self.push_image(src, None);
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.start_block(true);
+                style.bold = true;
+                style.heading = name[1..].parse().unwrap_or(1);
+                self.styled(element, style);
+                self.flush();
+            }
+            "p" | "figure" | "table" | "dl" => {
+                self.start_block(true);
+                self.walk(element);
+                self.flush();
+            }
+            "pre" => {
+                self.start_block(true);
+                self.pre += 1;
+                style.code = true;
+                self.styled(element, style);
+                self.flush();
+                self.pre -= 1;
+            }
+            "blockquote" => {
+                self.start_block(true);
+                self.containers.push(Container::Quote);
+                self.walk(element);
+                self.flush();
+                self.containers.pop();
+            }
+            "ul" | "ol" => {
+                // 嵌套的列表紧跟在列表项后面, 不需要空行
+                self.start_block(self.lists.is_empty());
+                let start = match name {
+                    "ol" => Some(
+                        element
+                            .value()
+                            .attr("start")
+                            .and_then(|start| start.trim().parse().ok())
+                            .unwrap_or(1),
+                    ),
+                    _ => None,
+                };
+                self.lists.push(start);
+                self.walk(element);
+                self.flush();
+                self.lists.pop();
+            }
+            "li" => {
+                self.flush();
+                let depth = self.lists.len().saturating_sub(1);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => BULLETS[depth % BULLETS.len()].to_string(),
+                };
+                self.containers.push(Container::Item {
+                    marker,
+                    used: false,
+                });
+                self.walk(element);
+                self.flush();
+                self.containers.pop();
+            }
+            "dd" => {
+                self.flush();
+                self.containers.push(Container::Indent);
+                self.walk(element);
+                self.flush();
+                self.containers.pop();
+            }
+            "dt" => {
+                self.flush();
+                style.bold = true;
+                self.styled(element, style);
+                self.flush();
+            }
+            // 脚注只在弹出窗口中显示
+            "aside" if has_type(element, "footnote") => {}
+            "div" | "section" | "article" | "main" | "header" | "footer" | "aside" | "nav"
+            | "figcaption" | "caption" | "address" | "tr" | "body" => {
+                self.flush();
+                self.walk(element);
+                self.flush();
+            }
+            "td" | "th" => {
+                // 同一行的单元格用两个空格分开
+                if !self.spans.is_empty() {
+                    self.push_span("  ".to_string(), TextStyle::default());
+                }
+                style.bold |= name == "th";
+                self.styled(element, style);
+            }
+            "em" | "i" | "cite" | "dfn" | "var" => {
+                style.italic = true;
+                self.styled(element, style);
+            }
+            "strong" | "b" => {
+                style.bold = true;
+                self.styled(element, style);
+            }
+            "code" | "kbd" | "samp" | "tt" => {
+                style.code = true;
+                self.styled(element, style);
+            }
+            "u" | "ins" => {
+                style.underline = true;
+                self.styled(element, style);
+            }
+            "s" | "del" | "strike" => {
+                style.strikethrough = true;
+                self.styled(element, style);
+            }
+            "a" if element.value().attr("href").is_some() => {
+                style.link = true;
+                self.links.push(Link {
+                    href: element.value().attr("href").unwrap_or_default().to_string(),
+                    is_note: has_type(element, "noteref"),
+                });
+
+                let outer = self.link.replace(self.links.len() - 1);
+                self.styled(element, style);
+                self.link = outer;
+            }
+            _ => self.walk(element),
+        }
+    }
+
+    fn style(&self) -> TextStyle {
+        self.styles.last().copied().unwrap_or_default()
+    }
+
+    fn styled(&mut self, element: ElementRef, style: TextStyle) {
+        self.styles.push(style);
+        self.walk(element);
+        self.styles.pop();
+    }
+
+    // A text node, its position in the document is recorded for CFIs
+    fn push_node(&mut self, node: NodeRef<Node>, text: &str) {
+        let (path, base) = text_path(node);
+
+        if self.pre > 0 {
+            let mut start = base;
+            for (index, segment) in text.split('\n').enumerate() {
+                if index > 0 {
+                    self.line_break();
+                }
+                self.push_source(&path, start, segment);
+                self.push_text(segment);
+                start += segment.encode_utf16().count() + 1;
+            }
+            return;
+        }
+
+        self.push_source(&path, base, text);
+        self.push_text(text);
+    }
+
+    fn push_source(&mut self, path: &[Step], start: usize, text: &str) {
+        self.sources.push((
+            self.lines.len(),
+            self.column(),
+            TextSource {
+                offset: 0,
+                path: path.to_vec(),
+                start,
+                text: text.to_string(),
+            },
+        ));
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let style = self.style();
+
+        if self.pre > 0 {
+            self.push_span(text.replace('\t', "    "), style);
+            return;
+        }
+
+        // 合并连续的空白, 行首不保留空白. 只合并 HTML 的 ASCII 空白, 全角空格和 &nbsp; 保留,
+        // 例如中文段落开头的两个全角空格
+        let mut collapsed = String::with_capacity(text.len());
+        let mut last_space = self
+            .spans
+            .last()
+            .map(|span| span.text.ends_with(' '))
+            .unwrap_or(true);
+
+        for c in text.chars() {
+            if c.is_ascii_whitespace() {
+                if !last_space {
+                    collapsed.push(' ');
+                    last_space = true;
+                }
+            } else {
+                collapsed.push(c);
+                last_space = false;
+            }
+        }
+
+        self.push_span(collapsed, style);
+    }
+
+    // Position in the current line
+    fn column(&self) -> usize {
+        self.spans.iter().map(|span| span.text.len()).sum()
+    }
+
+    // <ruby>漢<rt>かん</rt>字<rt>じ</rt></ruby> 或者 <rb>漢</rb><rt>かん</rt>: 注音对应它前面到上一个
+    // 注音为止的文字. 注音不输出到文本中, <rp> 的括号也不输出
+    fn ruby(&mut self, element: ElementRef) {
+        let line = self.lines.len();
+        let mut base = self.column();
+
+        for child in element.children() {
+            if self.done {
+                return;
+            }
+
+            match child.value() {
+                Node::Text(text) => self.push_node(child, text),
+                Node::Element(value) => {
+                    if Some(child.id()) == self.end {
+                        self.done = true;
+                        return;
+                    }
+                    let Some(child) = ElementRef::wrap(child) else {
+                        continue;
+                    };
+
+                    match value.name() {
+                        "rt" | "rtc" => {
+                            let text = child.text().collect::<Vec<_>>().join(" ");
+                            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                            let column = self.column();
+                            let content: String =
+                                self.spans.iter().map(|span| span.text.as_str()).collect();
+                            // 合并空白时留下的空格不属于注音的文字
+                            let piece = content.get(base..column).unwrap_or_default();
+                            let start = base + piece.len() - piece.trim_start().len();
+                            let end = base + piece.trim_end().len();
+
+                            if self.lines.len() == line && start < end && !text.is_empty() {
+                                self.ruby.push(Ruby {
+                                    range: start..end,
+                                    text,
+                                });
+                            }
+                            base = column;
+                        }
+                        "rp" => {}
+                        _ => self.element(child),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn push_span(&mut self, text: String, style: TextStyle) {
+        if text.is_empty() {
+            return;
+        }
+
+        let link = self.link;
+        match self.spans.last_mut() {
+            Some(last) if last.style == style && last.link == link => last.text.push_str(&text),
+            _ => self.spans.push(StyledSpan { text, style, link }),
+        }
+    }
+
+    // Finish the current line, nothing happens when it is empty
+    fn flush(&mut self) {
+        if let Some(last) = self.spans.last_mut() {
+            if self.pre == 0 {
+                let trimmed = last
+                    .text
+                    .trim_end_matches(|c: char| c.is_ascii_whitespace())
+                    .len();
+                last.text.truncate(trimmed);
+            }
+        }
+        self.spans.retain(|span| !span.text.is_empty());
+
+        if !self.spans.is_empty() {
+            self.push_line();
+        }
+        self.ruby.clear();
+    }
+
+    // <br> 和 <pre> 中的换行, 空行也要保留
+    fn line_break(&mut self) {
+        self.push_line();
+    }
+
+    // 图片单独占一行, 前后的文字分成两行
+    fn push_image(&mut self, src: &str, alt: Option<&str>) {
+        self.flush();
+
+        let alt = alt
+            .map(str::trim)
+            .filter(|alt| !alt.is_empty())
+            .unwrap_or("图片");
+        let (prefix, indent) = self.prefix(true);
+
+        self.lines.push(StyledLine {
+            prefix,
+            indent,
+            spans: vec![StyledSpan {
+                text: format!("[{}]", alt),
+                ..Default::default()
+            }],
+            kind: LineKind::Image,
+            image: src.to_string(),
+            ruby: vec![],
+        });
+    }
+
+    fn push_line(&mut self) {
+        let (prefix, indent) = self.prefix(!self.spans.is_empty());
+        let kind = if self.pre > 0 {
+            LineKind::Pre
+        } else {
+            LineKind::Text
+        };
+
+        // 行末的空白已经去掉
+        let length = self.column();
+        let mut ruby = std::mem::take(&mut self.ruby);
+        ruby.retain(|ruby| ruby.range.end <= length);
+
+        self.lines.push(StyledLine {
+            prefix,
+            indent,
+            spans: std::mem::take(&mut self.spans),
+            kind,
+            image: String::new(),
+            ruby,
+        });
+    }
+
+    // 块元素之间空一行
+    fn start_block(&mut self, gap: bool) {
+        self.flush();
+
+        // 列表项中的第一个段落紧跟在序号后面
+        let is_item_start = matches!(
+            self.containers.last(),
+            Some(Container::Item { used: false, .. })
+        );
+
+        if gap && !is_item_start && self.lines.last().is_some_and(|line| !line.is_blank()) {
+            let (_, indent) = self.prefix(false);
+            self.lines.push(StyledLine {
+                prefix: indent.clone(),
+                indent,
+                ..Default::default()
+            });
+        }
+    }
+
+    // Prefix of a new line and of its wrapped rows, the list marker is used by the first
+    // line with content
+    fn prefix(&mut self, use_marker: bool) -> (String, String) {
+        let mut prefix = String::new();
+        let mut indent = String::new();
+
+        for container in &mut self.containers {
+            match container {
+                Container::Quote => {
+                    prefix.push_str(QUOTE_BAR);
+                    indent.push_str(QUOTE_BAR);
+                }
+                Container::Item { marker, used } => {
+                    let blank = " ".repeat(marker.chars().count());
+                    if use_marker && !*used {
+                        prefix.push_str(marker);
+                        *used = true;
+                    } else {
+                        prefix.push_str(&blank);
+                    }
+                    indent.push_str(&blank);
+                }
+                Container::Indent => {
+                    prefix.push_str("    ");
+                    indent.push_str("    ");
+                }
+            }
+        }
+
+        (prefix, indent)
+    }
+}
+
+// epub:type="noteref" 或 role="doc-noteref"
+fn has_type(element: ElementRef, kind: &str) -> bool {
+    element.value().attrs().any(|(name, value)| match name {
+        "epub:type" => value.split_whitespace().any(|value| value == kind),
+        "role" => value
+            .split_whitespace()
+            .any(|value| value.strip_prefix("doc-") == Some(kind)),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_ideographic_space_and_nbsp() {
+        let text = StyledText::from_html(
+            "<html><body><p>\n\u{3000}\u{3000}第一段  正文\n</p><p>a&nbsp;&nbsp;b\t c</p></body></html>",
+        );
+        let lines: Vec<String> = text.lines.iter().map(StyledLine::content).collect();
+
+        assert_eq!(lines[0], "\u{3000}\u{3000}第一段 正文");
+        assert_eq!(lines.last().unwrap(), "a\u{a0}\u{a0}b c");
+    }
+
+    fn section(html: &str, start_id: &str, end_id: &str) -> Vec<String> {
+        StyledText::from_html_section(html, start_id, end_id)
+            .unwrap()
+            .lines
+            .iter()
+            .map(StyledLine::content)
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn section_ends_before_end_id() {
+        let html = "<html><body>\n<h2 id=\"a\">A</h2>\n<p>one</p>\n<h2 id=\"b\">B</h2>\n<p>two</p>\n</body></html>";
+
+        assert_eq!(section(html, "a", "b"), ["A", "one"]);
+        assert_eq!(section(html, "b", ""), ["B", "two"]);
+        assert_eq!(section(html, "", "b"), ["A", "one"]);
+        assert!(StyledText::from_html_section(html, "missing", "").is_none());
+    }
+
+    #[test]
+    fn section_ends_at_end_id_nested_in_start() {
+        let html = "<html><body>\n<section id=\"s\"><h2>A</h2><p>one</p>\n<section id=\"e\"><h3>B</h3><p id=\"p\">two</p></section>\n</section><p>after</p></body></html>";
+
+        assert_eq!(section(html, "s", "e"), ["A", "one"]);
+        assert_eq!(section(html, "e", ""), ["B", "two", "after"]);
+        // 被截断的 <section> 只记录自己, 结束锚点中的元素属于下一个小节
+        assert_eq!(section_ids(html, "s", "e").unwrap(), ["s"]);
+        assert_eq!(section_ids(html, "e", "").unwrap(), ["e", "p"]);
+    }
+
+    #[test]
+    fn section_of_minified_xhtml() {
+        let html = r#"<?xml version="1.0" encoding="utf-8"?><html xmlns="http://www.w3.org/1999/xhtml"><head><title>t</title></head><body><h1 id="c1">One</h1><p>a<b>b</b></p><h1 id="c2">Two</h1><p>c</p></body></html>"#;
+
+        assert_eq!(section(html, "c1", "c2"), ["One", "ab"]);
+        assert_eq!(section(html, "c2", ""), ["Two", "c"]);
+    }
+
+    #[test]
+    fn section_ends_at_end_id_in_hidden_footnote() {
+        let html = r##"<html xmlns:epub="http://www.idpf.org/2007/ops"><body><p id="s">text<a epub:type="noteref" href="#n1">1</a></p><aside epub:type="footnote" id="n1"><p id="e">note</p></aside><p>after</p></body></html>"##;
+
+        assert_eq!(section(html, "s", "e"), ["text1"]);
+        assert_eq!(section(html, "s", "n1"), ["text1"]);
+        assert_eq!(section_ids(html, "s", "e").unwrap(), ["s", "n1"]);
+    }
+}
@@ -1,10 +1,8 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
-use serde::Deserialize;
-use quick_xml::de::from_str;
-use quick_xml::{self, Reader};
 use crate::book::{Anchor, Toc};
+use crate::opf::resolve_href;
+use quick_xml::de::{from_str, DeError};
+use quick_xml::{self, Reader};
+use serde::Deserialize;
 
 #[derive(Debug, PartialEq, Default, Deserialize)]
 #[serde(default, rename_all = "PascalCase")]
@@ -30,42 +28,26 @@ pub struct NavPoint {
 }
 
 impl NavPoint {
-    pub fn get_toc(&self) -> Toc {
-        let children = self.build_children_toc(&self.nav_point);
+    // `href` is the manifest path of the NCX file, `src` attributes are relative to it
+    pub fn get_toc(&self, href: &str) -> Toc {
+        let children = match &self.nav_point {
+            Some(points) => points
+                .iter()
+                .map(|nav_point| nav_point.get_toc(href))
+                .collect(),
+            None => vec![],
+        };
 
         Toc {
-            path: self.content.get_pure_path(),
+            path: resolve_href(href, &self.content.get_pure_path()),
             title: self.nav_label.text.clone(),
             anchor: Anchor {
                 id: self.content.get_anchor_id(),
-                start_pos: 0,
-                end_pos: 0,
+                end_id: String::new(),
             },
             children,
         }
     }
-
-    fn build_children_toc(&self, nav_points: &Option<Vec<NavPoint>>) -> Vec<Toc> {
-        match nav_points {
-            Some(points) => points
-                .iter()
-                .map(|nav_point| {
-                    let sub_children = self.build_children_toc(&nav_point.nav_point);
-                    Toc {
-                        path: self.content.get_pure_path(),
-                        title: nav_point.nav_label.text.clone(),
-                        anchor: Anchor {
-                            id: nav_point.content.get_anchor_id(),
-                            start_pos: 0,
-                            end_pos: 0,
-                        },
-                        children: sub_children,
-                    }
-                })
-                .collect(),
-            None => vec![],
-        }
-    }
 }
 
 #[derive(Debug, PartialEq, Default, Deserialize)]
@@ -96,18 +78,10 @@ pub struct NavLabel {
 }
 
 impl Ncx {
-    pub fn parse(xml_text: &str) -> Self {
+    pub fn parse(xml_text: &str) -> Result<Self, DeError> {
         let mut reader = Reader::from_str(xml_text);
         reader.trim_text(true);
 
-        from_str(xml_text).unwrap()
-    }
-
-    pub fn read_text(path: &PathBuf) -> Self {
-        let mut file = File::open(path).unwrap();
-        let mut content = String::new();
-        file.read_to_string(&mut content).unwrap();
-
-        Ncx::parse(&content)
+        from_str(xml_text)
     }
 }
@@ -1,4 +1,10 @@
 pub mod app;
 pub mod event;
+pub mod image;
+pub mod keymap;
+pub mod layout;
+pub mod linebreak;
+pub mod link;
 pub mod render;
 pub mod show;
+pub mod terminal;
@@ -1,17 +1,172 @@
 use ratatui::widgets::ScrollbarState;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::book::Book;
+use serde::{Deserialize, Serialize};
+
+use super::image::Images;
+use super::keymap::{Keymap, DEFAULT_TIMEOUT};
+use super::layout::{line_offset, row_of_offset, wrap_text, WrappedLine};
+use super::link::{can_open, copy, open_external};
+use crate::config::{Config, Focus, RubyMode};
+use crate::explorer::bookmark::{self, Bookmark, Bookmarks};
+use crate::explorer::highlight::{Highlight, Highlights};
+use epub_parser::book::{Book, FlatToc};
+use epub_parser::{Result, SearchHit, StyledText};
+
+// 书签保存的开头文字的长度(字符数)
+const SNIPPET_LENGTH: usize = 40;
+
+#[derive(Default, PartialEq)]
+pub enum InputMode {
+    #[default]
+    Normal,
+    // 正在输入搜索内容
+    Search,
+    // 正在输入新书签的名称
+    BookmarkName,
+    // 正在输入高亮的注释
+    HighlightNote,
+}
+
+// 选择的起点和光标, 都是章节文本中的字节位置
+#[derive(Debug, Clone, Copy)]
+pub struct Visual {
+    anchor: usize,
+    cursor: usize,
+}
+
+#[derive(Clone, Copy)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    LineStart,
+    LineEnd,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReadingMode {
+    // 按大纲条目阅读
+    #[default]
+    Toc,
+    // 按 spine 顺序连续阅读, 大纲只用来跳转
+    Spine,
+}
+
+// 跳转前的位置, 用于返回
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    mode: ReadingMode,
+    selected: usize,
+    spine_index: usize,
+    offset: usize,
+}
 
 #[derive(Default)]
 pub struct App {
+    // 当前的大纲条目, 连续阅读时跟随当前文档
+    pub selected: usize,
+    pub mode: ReadingMode,
+    // 连续阅读模式下当前的 spine 文档
+    pub spine_index: usize,
+    // 当前显示的带样式的文本, context 是它的纯文本
+    pub content: StyledText,
+    pub context: String,
+    // 跳转后锚点在当前文本中的行
+    pub anchor_line: usize,
+    // 保存索引, 书签和高亮的缓存目录, 没有时为空
+    pub cache_dir: PathBuf,
     pub content_vertical_scroll_state: ScrollbarState,
     pub outline_vertical_scroll_state: ScrollbarState,
     pub content_vertical_scroll: usize,
     pub outline_vertical_scroll: usize,
     pub focus_content: bool,
+    // 大纲是否显示, 初始值来自配置
+    pub show_outline: bool,
+    // 按屏幕大小分页阅读, 滚动位置总是一页的开头
+    pub paginated: bool,
+    // 这本书选择的注音显示方式, 没有选择时使用配置
+    pub ruby: Option<RubyMode>,
+    pub config: Config,
+    pub input_mode: InputMode,
+    // 搜索框中的输入
+    pub search_input: String,
+    // 当前生效的搜索词, 用于高亮
+    pub search_query: String,
+    pub search_hits: Vec<SearchHit>,
+    pub search_selected: usize,
+    pub show_search_results: bool,
+    // 封面和书籍信息
+    pub show_info: bool,
+    // Tab 选中的链接, content.links 的下标
+    pub selected_link: Option<usize>,
+    // 弹出窗口中显示的注释
+    pub note: Option<StyledText>,
+    pub note_scroll: usize,
+    // 内容区域可以显示的行数
+    pub content_height: usize,
+    // 跟随链接前的位置, Ctrl-O 返回, Ctrl-I 前进
+    pub history: Vec<Position>,
+    pub forward: Vec<Position>,
+    // 状态栏中的提示, 按下任意键后清除
+    pub message: Option<String>,
+    // 按键绑定, 也用来显示提示中的按键
+    pub keymap: Keymap,
+    pub bookmarks: Vec<Bookmark>,
+    pub bookmark_input: String,
+    pub bookmark_selected: usize,
+    pub show_bookmarks: bool,
+    // 选择模式, 选中的文字可以保存为高亮
+    pub visual: Option<Visual>,
+    pub highlights: Vec<Highlight>,
+    pub highlight_input: String,
+    pub highlight_selected: usize,
+    pub show_highlights: bool,
+    // 按内容区域宽度折行后的章节文本
+    pub content_lines: Vec<WrappedLine>,
+    pub images: Images,
+    content_width: usize,
+    // 折行时章节文本的哈希, 用来判断是否需要重新折行
+    content_hash: u64,
 }
 
 impl App {
+    pub fn new(config: Config) -> Self {
+        App {
+            // 隐藏大纲时只能阅读内容
+            focus_content: config.focus == Focus::Content || !config.outline.visible,
+            show_outline: config.outline.visible,
+            paginated: config.content.paginated,
+            keymap: Keymap::new(
+                &config.keys,
+                config
+                    .key_timeout
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_TIMEOUT),
+            ),
+            config,
+            ..Default::default()
+        }
+    }
+
+    // Whether a popup covers the content, images of the content are not drawn over it
+    pub fn has_overlay(&self) -> bool {
+        self.show_search_results
+            || self.show_bookmarks
+            || self.show_highlights
+            || self.show_info
+            || self.note.is_some()
+    }
+
     pub fn content_up(&mut self) {
         self.content_vertical_scroll = self.content_vertical_scroll.saturating_sub(1);
         self.content_vertical_scroll_state = self
@@ -26,6 +181,131 @@ impl App {
             .position(self.content_vertical_scroll);
     }
 
+    pub fn scroll_content_to(&mut self, line: usize) {
+        self.content_vertical_scroll = line;
+        self.content_vertical_scroll_state = self
+            .content_vertical_scroll_state
+            .position(self.content_vertical_scroll);
+    }
+
+    // Wrap the chapter text for the content area, the first visible text stays on top
+    // when only the width changes
+    pub fn layout(&mut self, book: &Book, width: usize) {
+        let mut hasher = DefaultHasher::new();
+        self.context.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if hash == self.content_hash && width == self.content_width {
+            return;
+        }
+
+        let offset = (hash == self.content_hash).then(|| self.content_offset());
+        if hash != self.content_hash {
+            self.selected_link = None;
+        }
+
+        self.content_lines =
+            wrap_text(&self.content, width, &self.config.content, |href, width| {
+                self.images.size(book, href, width)
+            });
+        if self.config.content.line_spacing > 0 {
+            self.content_lines = add_spacing(
+                std::mem::take(&mut self.content_lines),
+                self.config.content.line_spacing,
+            );
+        }
+        self.content_width = width;
+        self.content_hash = hash;
+
+        if let Some(offset) = offset {
+            self.scroll_content_to(row_of_offset(&self.content_lines, offset));
+        }
+    }
+
+    // Wrap the text again after the layout options changed, keeping the first visible text
+    pub fn relayout(&mut self, book: &Book) {
+        let width = self.content_width;
+        self.content_width = 0;
+        self.layout(book, width);
+    }
+
+    pub fn content_width(&self) -> usize {
+        self.content_width
+    }
+
+    // 第一行可见内容在章节文本中的位置
+    pub fn content_offset(&self) -> usize {
+        self.content_lines
+            .get(self.content_vertical_scroll)
+            .map(|line| line.offset)
+            .unwrap_or(0)
+    }
+
+    // 当前章节(连续阅读时为 spine 文档)内的阅读进度, 0.0 ~ 1.0
+    pub fn chapter_progress(&self) -> f64 {
+        match self.context.len() {
+            0 => 0.0,
+            len => (self.content_offset() as f64 / len as f64).min(1.0),
+        }
+    }
+
+    // 全书的阅读进度, 按章节数估算
+    pub fn book_progress(&self, book: &Book) -> f64 {
+        let (index, count) = match self.mode {
+            ReadingMode::Toc => (self.selected, book.flat_toc().len()),
+            ReadingMode::Spine => (self.spine_index, book.spine().len()),
+        };
+
+        match count {
+            0 => 0.0,
+            count => ((index as f64 + self.chapter_progress()) / count as f64).min(1.0),
+        }
+    }
+
+    pub fn scroll_content_to_offset(&mut self, book: &Book, offset: usize) {
+        self.layout(book, self.content_width);
+        self.scroll_content_to(row_of_offset(&self.content_lines, offset));
+    }
+
+    // Scroll to a line of the unwrapped chapter text
+    pub fn scroll_content_to_line(&mut self, book: &Book, line: usize) {
+        self.scroll_content_to_offset(book, line_offset(&self.context, line));
+    }
+
+    // The current page and the number of pages of the chapter, counting from 1
+    pub fn page(&self) -> (usize, usize) {
+        let height = self.content_height.max(1);
+        let pages = self.content_lines.len().div_ceil(height).max(1);
+        (
+            (self.content_vertical_scroll / height + 1).min(pages),
+            pages,
+        )
+    }
+
+    pub fn scroll_content_to_page(&mut self, page: usize) {
+        let (_, pages) = self.page();
+        let page = page.clamp(1, pages);
+        self.scroll_content_to((page - 1) * self.content_height);
+    }
+
+    // 分页阅读时滚动到第一行可见内容所在页的开头, 窗口大小变化后这一行仍然可见
+    pub fn snap_to_page(&mut self) {
+        let height = self.content_height.max(1);
+        let scroll = self.content_vertical_scroll;
+        self.scroll_content_to(scroll - scroll % height);
+    }
+
+    // Keep the scroll inside the text after the content area changed size
+    pub fn clamp_content_scroll(&mut self) {
+        if self.paginated {
+            let (page, _) = self.page();
+            self.scroll_content_to_page(page);
+        } else {
+            let last = self.content_lines.len().saturating_sub(self.content_height);
+            self.scroll_content_to(self.content_vertical_scroll.min(last));
+        }
+    }
+
     pub fn reset_content_scroll(&mut self) {
         self.content_vertical_scroll = 0;
         self.content_vertical_scroll_state = self
@@ -33,38 +313,920 @@ impl App {
             .position(self.content_vertical_scroll);
     }
 
-    pub fn outline_up(&mut self) {
-        self.outline_vertical_scroll = self.outline_vertical_scroll.saturating_sub(1);
+    pub fn reset_outline_scroll(&mut self) {
+        self.outline_vertical_scroll = 0;
         self.outline_vertical_scroll_state = self
             .outline_vertical_scroll_state
             .position(self.outline_vertical_scroll);
-
-        self.reset_content_scroll();
     }
 
-    pub fn outline_down(&mut self) {
-        self.outline_vertical_scroll = self.outline_vertical_scroll.saturating_add(1);
+    pub fn scroll_outline_to(&mut self, line: usize) {
+        self.outline_vertical_scroll = line;
         self.outline_vertical_scroll_state = self
             .outline_vertical_scroll_state
             .position(self.outline_vertical_scroll);
+    }
 
-        self.reset_content_scroll();
+    pub fn search(&mut self, book: &Book) {
+        self.search_query = self.search_input.trim().to_string();
+        self.search_hits = match self.mode {
+            ReadingMode::Toc => book.search(&self.search_query),
+            ReadingMode::Spine => book.search_spine(&self.search_query),
+        };
+        self.search_selected = 0;
+        self.show_search_results = !self.search_hits.is_empty();
     }
 
-    pub fn reset_outline_scroll(&mut self) {
-        self.outline_vertical_scroll = 0;
-        self.outline_vertical_scroll_state = self
-            .outline_vertical_scroll_state
-            .position(self.outline_vertical_scroll);
+    pub fn clear_search(&mut self) {
+        self.search_query.clear();
+        self.search_hits.clear();
+        self.search_selected = 0;
+        self.show_search_results = false;
+    }
+
+    // Open the chapter of a search hit and scroll to its line
+    pub fn jump_to_hit(&mut self, book: &Book, index: usize) {
+        let Some(hit) = self.search_hits.get(index).cloned() else {
+            return;
+        };
+        self.search_selected = index;
+
+        match self.mode {
+            ReadingMode::Toc => {
+                self.selected = hit.index;
+                self.read_and_show_text(book);
+            }
+            ReadingMode::Spine => {
+                self.open_spine_document(book, hit.index);
+            }
+        }
+
+        self.scroll_outline_to(self.selected);
+        self.focus_content = true;
+        self.scroll_content_to_line(book, hit.line);
+    }
+
+    pub fn search_next(&mut self, book: &Book) {
+        if !self.search_hits.is_empty() {
+            let index = (self.search_selected + 1) % self.search_hits.len();
+            self.jump_to_hit(book, index);
+        }
+    }
+
+    pub fn search_prev(&mut self, book: &Book) {
+        if !self.search_hits.is_empty() {
+            let len = self.search_hits.len();
+            let index = (self.search_selected + len - 1) % len;
+            self.jump_to_hit(book, index);
+        }
     }
 
-    pub fn go_top(&mut self, book: &mut Book) {
+    // Select the next (or previous) link on the screen
+    pub fn cycle_link(&mut self, forward: bool) {
+        let start = self.content_vertical_scroll.min(self.content_lines.len());
+        let end = (start + self.content_height).min(self.content_lines.len());
+
+        let mut visible: Vec<usize> = vec![];
+        for span in self.content_lines[start..end]
+            .iter()
+            .flat_map(|row| &row.spans)
+        {
+            let Some(link) = span.link else {
+                continue;
+            };
+            if link < self.content.links.len() && !visible.contains(&link) {
+                visible.push(link);
+            }
+        }
+
+        // 当前选中的链接不在屏幕上时从头(或从尾)开始
+        let position = self
+            .selected_link
+            .and_then(|link| visible.iter().position(|other| *other == link));
+        let next = match (position, forward) {
+            (Some(index), true) => visible.get(index + 1).or(visible.first()),
+            (Some(index), false) => match index {
+                0 => visible.last(),
+                index => visible.get(index - 1),
+            },
+            (None, true) => visible.first(),
+            (None, false) => visible.last(),
+        };
+
+        self.selected_link = next.copied();
+    }
+
+    // Activate the selected link: show a footnote, follow a link inside the book or open an
+    // external one
+    pub fn activate_link(&mut self, book: &Book) {
+        let lang = self.config.language;
+        let Some(link) = self
+            .selected_link
+            .and_then(|link| self.content.links.get(link))
+            .cloned()
+        else {
+            return;
+        };
+
+        if link.is_note {
+            // 脚注在弹出窗口中显示, 不改变阅读位置
+            self.note = Some(
+                book.note(&link.href)
+                    .unwrap_or_else(|e| StyledText::plain(&e.to_string())),
+            );
+            self.note_scroll = 0;
+        } else if link.is_external() && !can_open(&link.href) {
+            // 不打开其他协议的链接, 复制后由用户决定
+            self.message = Some(match copy(&link.href) {
+                Ok(()) => format!(
+                    "{}: {}",
+                    lang.text(
+                        "只打开 http(s) 和 mailto 链接, 已复制",
+                        "Only http(s) and mailto links are opened, copied"
+                    ),
+                    link.href
+                ),
+                Err(_) => format!(
+                    "{}: {}",
+                    lang.text("不打开这个链接", "Not opening the link"),
+                    link.href
+                ),
+            });
+        } else if link.is_external() {
+            self.message = Some(match open_external(&link.href) {
+                Ok(()) => format!("{}: {}", lang.text("已打开", "Opened"), link.href),
+                // 无法打开时复制到剪贴板
+                Err(e) => match copy(&link.href) {
+                    Ok(()) => format!(
+                        "{} ({}), {}: {}",
+                        lang.text("无法打开链接", "Cannot open the link"),
+                        e,
+                        lang.text("已复制", "copied"),
+                        link.href
+                    ),
+                    Err(_) => format!(
+                        "{}: {}",
+                        lang.text("无法打开链接", "Cannot open the link"),
+                        e
+                    ),
+                },
+            });
+        } else {
+            let position = self.position();
+            if self.open_href(book, &link.href) {
+                self.history.push(position);
+                self.forward.clear();
+
+                self.scroll_outline_to(self.selected);
+                self.focus_content = true;
+                self.scroll_content_to_line(book, self.anchor_line);
+            } else {
+                self.message = Some(format!(
+                    "{}: {}",
+                    lang.text("书中没有找到", "Not in the book"),
+                    link.href
+                ));
+            }
+        }
+    }
+
+    // Copy the selected link to the clipboard
+    pub fn copy_link(&mut self) {
+        let lang = self.config.language;
+        let Some(link) = self
+            .selected_link
+            .and_then(|link| self.content.links.get(link))
+        else {
+            return;
+        };
+
+        self.message = Some(match copy(&link.href) {
+            Ok(()) => format!("{}: {}", lang.text("已复制", "Copied"), link.href),
+            Err(e) => format!("{}: {}", lang.text("无法复制", "Cannot copy"), e),
+        });
+    }
+
+    // Bookmark the first visible line, named after the chapter when no name is given
+    pub fn add_bookmark(&mut self, book: &Book) {
+        let Some((path, anchor, chapter)) = self.current_section(book) else {
+            return;
+        };
+
+        // 从第一个可见的文字开始, 跳过空行
+        let rest = &self.context[self.content_offset().min(self.context.len())..];
+        let trimmed = rest.trim_start();
+        let offset = self.context.len() - trimmed.len();
+        let snippet: String = trimmed
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .take(SNIPPET_LENGTH)
+            .collect();
+
+        let name = match self.bookmark_input.trim() {
+            "" => chapter.clone(),
+            name => name.to_string(),
+        };
+
+        self.bookmarks.push(Bookmark {
+            name: name.clone(),
+            chapter,
+            path,
+            anchor,
+            mode: self.mode,
+            offset,
+            cfi: self.current_cfi(book, offset).unwrap_or_default(),
+            snippet,
+            created: bookmark::now(),
+        });
+        let message = format!(
+            "{}: {}",
+            self.config.language.text("已添加书签", "Bookmark added"),
+            name
+        );
+        let bookmarks = Bookmarks {
+            items: self.bookmarks.clone(),
+        };
+        self.persist(|dir| bookmarks.save(dir), message);
+    }
+
+    pub fn delete_bookmark(&mut self) {
+        if self.bookmark_selected >= self.bookmarks.len() {
+            return;
+        }
+
+        let bookmark = self.bookmarks.remove(self.bookmark_selected);
+        self.bookmark_selected = self
+            .bookmark_selected
+            .min(self.bookmarks.len().saturating_sub(1));
+        let message = format!(
+            "{}: {}",
+            self.config.language.text("已删除书签", "Bookmark deleted"),
+            bookmark.name
+        );
+        let bookmarks = Bookmarks {
+            items: self.bookmarks.clone(),
+        };
+        self.persist(|dir| bookmarks.save(dir), message);
+    }
+
+    // Open the chapter of a bookmark and scroll to it, the snippet finds the place again when
+    // the chapter changed
+    pub fn jump_to_bookmark(&mut self, book: &Book, index: usize) {
+        let Some(bookmark) = self.bookmarks.get(index).cloned() else {
+            return;
+        };
+        if self.open_section(
+            book,
+            &bookmark.path,
+            &bookmark.anchor,
+            &bookmark.chapter,
+            bookmark.mode,
+        ) {
+            let hint = self
+                .cfi_offset(book, &bookmark.cfi)
+                .unwrap_or(bookmark.offset);
+            let offset = bookmark::locate(&self.context, &bookmark.snippet, hint);
+            self.scroll_content_to_offset(book, offset);
+        }
+    }
+
+    // Open a saved section, remembering the current position in the history
+    fn open_section(
+        &mut self,
+        book: &Book,
+        path: &str,
+        anchor: &str,
+        chapter: &str,
+        mode: ReadingMode,
+    ) -> bool {
+        let position = self.position();
+
+        let found = match mode {
+            ReadingMode::Toc => {
+                let same_section = |toc: &FlatToc| toc.path == path && toc.anchor.id == anchor;
+                // 同一小节有多个大纲条目时按标题区分
+                let entry = book
+                    .flat_toc()
+                    .iter()
+                    .position(|toc| same_section(toc) && toc.title == chapter)
+                    .or_else(|| book.flat_toc().iter().position(same_section))
+                    .or_else(|| book.flat_toc().iter().position(|toc| toc.path == path));
+
+                entry.map(|entry| {
+                    self.mode = ReadingMode::Toc;
+                    self.selected = entry;
+                    self.read_and_show_text(book);
+                })
+            }
+            ReadingMode::Spine => {
+                book.spine()
+                    .iter()
+                    .position(|item| item.href == path)
+                    .map(|index| {
+                        self.mode = ReadingMode::Spine;
+                        self.open_spine_document(book, index);
+                    })
+            }
+        };
+
+        if found.is_none() {
+            self.message = Some(format!(
+                "{}: {}",
+                self.config.language.text("书中没有找到", "Not in the book"),
+                path
+            ));
+            return false;
+        }
+
+        self.history.push(position);
+        self.forward.clear();
+
+        self.scroll_outline_to(self.selected);
+        self.focus_content = true;
+        true
+    }
+
+    // Save with `save` into the cache directory and show the message, with the error when saving
+    // fails
+    fn persist(&mut self, save: impl FnOnce(&Path) -> io::Result<()>, message: String) {
+        // 没有缓存目录时书签和高亮只在本次阅读中有效
+        let result = match self.cache_dir.as_os_str().is_empty() {
+            true => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                self.config
+                    .language
+                    .text("没有缓存目录", "no cache directory"),
+            )),
+            false => save(&self.cache_dir),
+        };
+
+        self.message = Some(match result {
+            Ok(()) => message,
+            Err(e) => format!(
+                "{}, {}: {}",
+                message,
+                self.config
+                    .language
+                    .text("但无法保存", "but it cannot be saved"),
+                e
+            ),
+        });
+    }
+
+    // Start a selection at the first visible text, after the prefix of quotes and lists
+    pub fn start_visual(&mut self) {
+        let start = self.content_vertical_scroll.min(self.content_lines.len());
+        let offset = self.content_lines[start..]
+            .iter()
+            .find(|row| !row.text().trim().is_empty())
+            .map(|row| row.text_offset + (row.text().len() - row.text().trim_start().len()))
+            .unwrap_or_else(|| self.content_offset());
+
+        self.focus_content = true;
+        self.visual = Some(Visual {
+            anchor: offset,
+            cursor: offset,
+        });
+    }
+
+    pub fn move_cursor(&mut self, motion: Motion) {
+        let Some(visual) = self.visual else {
+            return;
+        };
+        let text = self.context.as_str();
+        let cursor = visual.cursor.min(text.len());
+
+        let next_char = |offset: usize| {
+            text[offset..]
+                .chars()
+                .next()
+                .map(|c| offset + c.len_utf8())
+                .filter(|next| *next < text.len())
+                .unwrap_or(offset)
+        };
+        let prev_char = |offset: usize| {
+            text[..offset]
+                .chars()
+                .next_back()
+                .map(|c| offset - c.len_utf8())
+                .unwrap_or(offset)
+        };
+
+        let row = self.row_of_text(cursor);
+        let cursor = match motion {
+            Motion::Left => prev_char(cursor),
+            Motion::Right => next_char(cursor),
+            Motion::Up | Motion::Down => {
+                // 跳过行间距的空行
+                let lines = &self.content_lines;
+                let target = match motion {
+                    Motion::Up => (0..row).rev().find(|&other| !lines[other].spacer),
+                    _ => (row + 1..lines.len()).find(|&other| !lines[other].spacer),
+                };
+
+                match (target, self.content_lines.get(row)) {
+                    (Some(target), Some(current)) => {
+                        // 尽量保持在同一列
+                        let column = cursor.saturating_sub(current.text_offset);
+                        let target = &self.content_lines[target];
+                        let offset = target.text_offset + column.min(target.text().len());
+                        bookmark::floor_char_boundary(
+                            text,
+                            offset.min(text.len().saturating_sub(1)),
+                        )
+                    }
+                    _ => cursor,
+                }
+            }
+            Motion::WordForward => word_forward(text, cursor),
+            Motion::WordBackward => word_backward(text, cursor),
+            Motion::LineStart => self
+                .content_lines
+                .get(row)
+                .map(|row| row.text_offset)
+                .unwrap_or(cursor),
+            Motion::LineEnd => self
+                .content_lines
+                .get(row)
+                .map(|row| prev_char(row.text_offset + row.text().len()).max(row.text_offset))
+                .unwrap_or(cursor),
+        };
+
+        let forward = matches!(
+            motion,
+            Motion::Right | Motion::Down | Motion::WordForward | Motion::LineEnd
+        );
+        let cursor = self.skip_prefix(cursor, forward);
+        self.visual = Some(Visual { cursor, ..visual });
+
+        // 光标移出屏幕时滚动
+        let row = self.row_of_text(cursor);
+        if row < self.content_vertical_scroll {
+            self.scroll_content_to(row);
+        } else if row >= self.content_vertical_scroll + self.content_height {
+            self.scroll_content_to(row + 1 - self.content_height.max(1));
+        }
+    }
+
+    // 光标不停在引用和列表的前缀中: 向前移动到这一行的文字, 向后移动到上一行的末尾
+    fn skip_prefix(&self, cursor: usize, forward: bool) -> usize {
+        let Some(row) = self
+            .content_lines
+            .iter()
+            .find(|row| row.offset <= cursor && cursor < row.text_offset)
+        else {
+            return cursor;
+        };
+
+        match forward || row.offset == 0 {
+            true => row.text_offset,
+            false => row.offset - 1,
+        }
+    }
+
+    // Byte range of the selection in the chapter text, the character under the cursor included
+    pub fn selection(&self) -> Option<Range<usize>> {
+        let visual = self.visual?;
+        let start = visual.anchor.min(visual.cursor).min(self.context.len());
+        let last = visual.anchor.max(visual.cursor).min(self.context.len());
+        let end = self.context[last..]
+            .chars()
+            .next()
+            .map(|c| last + c.len_utf8())
+            .unwrap_or(last);
+
+        Some(start..end)
+    }
+
+    pub fn cursor(&self) -> Option<usize> {
+        self.visual.map(|visual| visual.cursor)
+    }
+
+    // Save the selection as a highlight with the note that was typed
+    pub fn add_highlight(&mut self, book: &Book) {
+        let (Some(range), Some((path, anchor, chapter))) =
+            (self.selection(), self.current_section(book))
+        else {
+            return;
+        };
+
+        self.highlights.push(Highlight {
+            text: self.context[range.clone()].to_string(),
+            note: self.highlight_input.trim().to_string(),
+            chapter,
+            path,
+            anchor,
+            mode: self.mode,
+            offset: range.start,
+            cfi: self.current_cfi(book, range.start).unwrap_or_default(),
+            created: bookmark::now(),
+        });
+        self.visual = None;
+        let message = self
+            .config
+            .language
+            .text("已保存高亮", "Highlight saved")
+            .to_string();
+        let highlights = Highlights {
+            items: self.highlights.clone(),
+        };
+        self.persist(|dir| highlights.save(dir), message);
+    }
+
+    pub fn copy_selection(&mut self) {
+        let lang = self.config.language;
+        let Some(range) = self.selection() else {
+            return;
+        };
+
+        self.message = Some(match copy(&self.context[range]) {
+            Ok(()) => lang
+                .text("已复制选中的文字", "Selection copied")
+                .to_string(),
+            Err(e) => format!("{}: {}", lang.text("无法复制", "Cannot copy"), e),
+        });
+        self.visual = None;
+    }
+
+    // Ranges of the saved highlights in the current chapter text
+    pub fn highlight_ranges(&self, book: &Book) -> Vec<Range<usize>> {
+        let Some((path, _, _)) = self.current_section(book) else {
+            return vec![];
+        };
+
+        self.highlights
+            .iter()
+            .filter(|highlight| highlight.path == path)
+            .filter_map(|highlight| {
+                let hint = self
+                    .cfi_offset(book, &highlight.cfi)
+                    .unwrap_or(highlight.offset);
+                let start = bookmark::locate(&self.context, &highlight.text, hint);
+                // 高亮的文字不在当前章节中
+                self.context[start..]
+                    .starts_with(&highlight.text)
+                    .then(|| start..start + highlight.text.len())
+            })
+            .collect()
+    }
+
+    pub fn delete_highlight(&mut self) {
+        if self.highlight_selected >= self.highlights.len() {
+            return;
+        }
+
+        self.highlights.remove(self.highlight_selected);
+        self.highlight_selected = self
+            .highlight_selected
+            .min(self.highlights.len().saturating_sub(1));
+        let message = self
+            .config
+            .language
+            .text("已删除高亮", "Highlight deleted")
+            .to_string();
+        let highlights = Highlights {
+            items: self.highlights.clone(),
+        };
+        self.persist(|dir| highlights.save(dir), message);
+    }
+
+    pub fn jump_to_highlight(&mut self, book: &Book, index: usize) {
+        let Some(highlight) = self.highlights.get(index).cloned() else {
+            return;
+        };
+
+        if self.open_section(
+            book,
+            &highlight.path,
+            &highlight.anchor,
+            &highlight.chapter,
+            highlight.mode,
+        ) {
+            let hint = self
+                .cfi_offset(book, &highlight.cfi)
+                .unwrap_or(highlight.offset);
+            let offset = bookmark::locate(&self.context, &highlight.text, hint);
+            self.scroll_content_to_offset(book, offset);
+        }
+    }
+
+    // Write the highlights to <title>-highlights.md (or .json) in the working directory
+    pub fn export_highlights(&mut self, book: &Book, json: bool) {
+        let lang = self.config.language;
+        let highlights = Highlights {
+            items: self.highlights.clone(),
+        };
+        let name: String = book
+            .title()
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c => c,
+            })
+            .collect();
+
+        let (file, content) = match json {
+            true => (
+                format!("{}-highlights.json", name),
+                highlights.to_json(book).map_err(io::Error::from),
+            ),
+            false => (
+                format!("{}-highlights.md", name),
+                Ok(highlights.to_markdown(book)),
+            ),
+        };
+
+        self.message = Some(
+            match content.and_then(|content| fs::write(&file, content)) {
+                Ok(()) => format!("{}: {}", lang.text("已导出", "Exported"), file),
+                Err(e) => format!("{} {}: {}", lang.text("无法导出", "Cannot export"), file, e),
+            },
+        );
+    }
+
+    // 文字位置所在的行
+    fn row_of_text(&self, offset: usize) -> usize {
+        self.content_lines
+            .partition_point(|row| row.text_offset <= offset)
+            .saturating_sub(1)
+    }
+
+    // Return to where the last followed link was activated
+    pub fn go_back(&mut self, book: &Book) {
+        if let Some(position) = self.history.pop() {
+            self.forward.push(self.position());
+            self.restore(book, position);
+        }
+    }
+
+    pub fn go_forward(&mut self, book: &Book) {
+        if let Some(position) = self.forward.pop() {
+            self.history.push(self.position());
+            self.restore(book, position);
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            mode: self.mode,
+            selected: self.selected,
+            spine_index: self.spine_index,
+            offset: self.content_offset(),
+        }
+    }
+
+    fn restore(&mut self, book: &Book, position: Position) {
+        self.mode = position.mode;
+        self.selected = position.selected;
+        self.spine_index = position.spine_index;
+        self.reopen(book);
+        // 连续阅读时打开文档会把大纲移到文档的第一个条目
+        self.selected = position.selected;
+
+        self.scroll_outline_to(self.selected);
+        self.scroll_content_to_offset(book, position.offset);
+    }
+
+    // 当前章节所在的文件, 大纲锚点(连续阅读模式下为空)和标题
+    fn current_section(&self, book: &Book) -> Option<(String, String, String)> {
+        let toc = book.flat_toc().get(self.selected)?;
+
+        match self.mode {
+            ReadingMode::Toc => Some((toc.path.clone(), toc.anchor.id.clone(), toc.title.clone())),
+            ReadingMode::Spine => book
+                .spine()
+                .get(self.spine_index)
+                .map(|item| (item.href.clone(), String::new(), toc.title.clone())),
+        }
+    }
+
+    // Show the selected TOC entry, in continuous reading mode the spine document it is in
+    pub fn read_and_show_text(&mut self, book: &Book) {
+        let Some(toc) = book.flat_toc().get(self.selected).cloned() else {
+            return;
+        };
+
+        match self.mode {
+            ReadingMode::Toc => {
+                self.show(book.chapter_styled(self.selected));
+                self.anchor_line = 0;
+            }
+            ReadingMode::Spine => {
+                // 大纲只用来跳转: 打开所在的 spine 文档, 定位到锚点所在的行
+                if let Some(index) = book.spine_position(&toc.path) {
+                    self.spine_index = index;
+                }
+                self.show(book.spine_styled(self.spine_index));
+
+                // 锚点之前的内容渲染后的行数
+                self.anchor_line = match toc.anchor.id.as_str() {
+                    "" => 0,
+                    id => lines_before(book, &toc.path, "", id),
+                };
+            }
+        }
+    }
+
+    // Reopen the text that was being read, the spine document in continuous reading mode
+    pub fn reopen(&mut self, book: &Book) {
+        match self.mode {
+            ReadingMode::Spine if self.spine_index < book.spine().len() => {
+                self.open_spine_document(book, self.spine_index);
+            }
+            _ => self.read_and_show_text(book),
+        }
+    }
+
+    // Open the document a resolved link of the chapter points to, `href` is `path#id`. The
+    // line of the target element is left in anchor_line. Returns false when the file is not
+    // part of the book.
+    fn open_href(&mut self, book: &Book, href: &str) -> bool {
+        let Some(target) = book.link_target(href) else {
+            return false;
+        };
+
+        if let (ReadingMode::Toc, Some(entry)) = (self.mode, target.entry) {
+            self.selected = entry;
+            self.read_and_show_text(book);
+
+            let start = &book.flat_toc()[entry].anchor.id;
+            self.anchor_line = match target.id.as_str() {
+                "" => 0,
+                id if id == start => 0,
+                id => lines_before(book, &target.path, start, id),
+            };
+            return true;
+        }
+
+        // 不在大纲中的文档只能按 spine 连续阅读
+        let Some(index) = target.spine_index else {
+            return false;
+        };
+        self.mode = ReadingMode::Spine;
+        self.open_spine_document(book, index);
+
+        self.anchor_line = match target.id.as_str() {
+            "" => 0,
+            id => lines_before(book, &target.path, "", id),
+        };
+        true
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            ReadingMode::Toc => ReadingMode::Spine,
+            ReadingMode::Spine => ReadingMode::Toc,
+        };
+    }
+
+    // Flow into the next linear spine document, returns false at the end of the book
+    pub fn next_document(&mut self, book: &Book) -> bool {
+        let spine = book.spine();
+        match (self.spine_index + 1..spine.len()).find(|&i| spine[i].linear) {
+            Some(index) => self.open_spine_document(book, index),
+            None => false,
+        }
+    }
+
+    // Flow back into the previous linear spine document
+    pub fn prev_document(&mut self, book: &Book) -> bool {
+        let spine = book.spine();
+        match (0..self.spine_index).rev().find(|&i| spine[i].linear) {
+            Some(index) => self.open_spine_document(book, index),
+            None => false,
+        }
+    }
+
+    pub fn open_spine_document(&mut self, book: &Book, index: usize) -> bool {
+        self.spine_index = index;
+        self.show(book.spine_styled(index));
+        self.anchor_line = 0;
+
+        // 大纲高亮跟随当前文档
+        if let Some(href) = book.spine().get(index).map(|item| &item.href) {
+            if let Some(selected) = book.flat_toc().iter().position(|toc| toc.path == *href) {
+                self.selected = selected;
+            }
+        }
+
+        true
+    }
+
+    // 读取失败时在内容区显示原因, 而不是退出阅读器
+    fn show(&mut self, result: Result<StyledText>) {
+        self.content = result.unwrap_or_else(|e| StyledText::plain(&e.to_string()));
+        self.context = self.content.to_string();
+    }
+
+    // The CFI of a byte offset in the shown text
+    pub fn current_cfi(&self, book: &Book, offset: usize) -> Option<String> {
+        book.cfi_in(self.shown_spine_index(book)?, &self.content, offset)
+    }
+
+    // The byte offset in the shown text a CFI points to, None when it is in another document
+    // or outside the shown section
+    pub fn cfi_offset(&self, book: &Book, cfi: &str) -> Option<usize> {
+        book.cfi_offset_in(cfi, self.shown_spine_index(book)?, &self.content)
+    }
+
+    // 大纲模式下是当前条目所在的文档
+    fn shown_spine_index(&self, book: &Book) -> Option<usize> {
+        match self.mode {
+            ReadingMode::Spine => Some(self.spine_index).filter(|&i| i < book.spine().len()),
+            ReadingMode::Toc => book.spine_position(&book.flat_toc().get(self.selected)?.path),
+        }
+    }
+
+    pub fn go_top(&mut self, book: &Book) {
         if self.focus_content {
             self.reset_content_scroll();
         } else {
             self.reset_outline_scroll();
-            book.selected = 0;
-            book.read_and_show_text();
+            self.selected = 0;
+            self.read_and_show_text(book);
+        }
+    }
+}
+
+// 从锚点 `start` 到锚点 `id` 的内容渲染后的行数
+fn lines_before(book: &Book, path: &str, start: &str, id: &str) -> usize {
+    book.section_styled(path, start, id)
+        .map(|text| text.lines.len())
+        .unwrap_or(0)
+}
+
+// Insert `spacing` blank rows after every row of text. The blank rows keep the offsets of the
+// row above, so positions still find the row with the text.
+fn add_spacing(rows: Vec<WrappedLine>, spacing: usize) -> Vec<WrappedLine> {
+    let mut spaced = Vec::with_capacity(rows.len() * (spacing + 1));
+
+    for (index, row) in rows.iter().enumerate() {
+        spaced.push(row.clone());
+
+        // 图片的各行之间和空行之后不加
+        let is_blank = row.image.is_none() && row.spans.is_empty();
+        let inside_image = row
+            .image
+            .as_ref()
+            .is_some_and(|image| image.row + 1 < image.rows);
+        if is_blank || inside_image || index + 1 == rows.len() {
+            continue;
         }
+
+        for _ in 0..spacing {
+            spaced.push(WrappedLine {
+                offset: row.offset,
+                text_offset: row.text_offset + row.text().len(),
+                spacer: true,
+                ..Default::default()
+            });
+        }
+    }
+
+    spaced
+}
+
+// The start of the next word after `cursor`, or `cursor` in the last word
+fn word_forward(text: &str, cursor: usize) -> usize {
+    let rest = &text[cursor..];
+    let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    match rest[word_end..].find(|c: char| !c.is_whitespace()) {
+        Some(index) => cursor + word_end + index,
+        None => cursor,
+    }
+}
+
+// The start of the word before `cursor`. 空白可能是多字节字符, 例如全角空格
+fn word_backward(text: &str, cursor: usize) -> usize {
+    text[..cursor]
+        .trim_end()
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map(|(index, c)| index + c.len_utf8())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_motions_with_cjk_spaces() {
+        let text = "你好\u{3000}世界\u{a0}abc def";
+        let world = text.find('世').unwrap();
+        let abc = text.find("abc").unwrap();
+        let def = text.find("def").unwrap();
+
+        assert_eq!(word_backward(text, def), abc);
+        assert_eq!(word_backward(text, abc), world);
+        assert_eq!(word_backward(text, world), 0);
+        assert_eq!(word_backward(text, 0), 0);
+
+        assert_eq!(word_forward(text, 0), world);
+        assert_eq!(word_forward(text, world), abc);
+        assert_eq!(word_forward(text, def), def);
     }
 }
@@ -1,110 +1,591 @@
+use super::app::{App, InputMode, Motion, ReadingMode};
+use super::keymap::{Action, KeyMode};
+use super::terminal::{self, Signals};
 use super::{app, render::render};
-use crate::book::Book;
+use crate::config::{Config, RubyMode};
+use crate::explorer::bookmark::Bookmarks;
+use crate::explorer::highlight::Highlights;
+use crate::explorer::index::Explorer;
 use crossterm::event;
 use crossterm::event::{Event, KeyCode};
+use epub_parser::book::Book;
 use ratatui::backend::CrosstermBackend;
 use ratatui::Terminal;
-use std::io::{self, stdout};
-use std::time::Duration;
+use std::io::{self, stdout, Stdout};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-pub fn handle_events(book: &mut Book) -> io::Result<()> {
-    let mut chapter_input = String::new();
+// 阅读位置的自动保存间隔
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
 
+pub fn handle_events(explorer: &Explorer, cache_dir: PathBuf, config: Config) -> io::Result<()> {
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let book = &explorer.book;
+    let (position, cfi) = (explorer.position, explorer.cfi.as_str());
 
-    let mut app = app::App::default();
+    let mut app = app::App::new(config);
+    app.selected = explorer.selected;
+    app.mode = explorer.mode;
+    app.spine_index = explorer.spine_index;
+    app.cache_dir = cache_dir;
+    if let Some(mode) = explorer.ruby {
+        app.config.content.ruby = mode;
+    }
+    app.ruby = explorer.ruby;
+    if !app.cache_dir.as_os_str().is_empty() {
+        app.bookmarks = Bookmarks::load(&app.cache_dir).items;
+        app.highlights = Highlights::load(&app.cache_dir).items;
+    }
+
+    // 打开时先显示封面和书籍信息, 按任意键关闭
+    app.show_info = true;
+
+    // 恢复上次的阅读位置, 先绘制一次得到内容区域的宽度再定位
+    app.reopen(book);
+    app.scroll_outline_to(app.selected);
+    draw(&mut terminal, book, &mut app)?;
+    // 章节内容变化后 CFI 仍然指向原来的文字
+    let position = app.cfi_offset(book, cfi).unwrap_or(position);
+    app.scroll_content_to_offset(book, position);
+
+    let signals = Signals::register()?;
+
+    let mut saved = (app.selected, position);
+    let mut last_save = Instant::now();
 
     loop {
+        if signals.terminate() {
+            save_position(book, &app);
+            break;
+        }
+        if signals.suspend() {
+            suspend(&mut terminal, &mut app)?;
+        }
+
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            if saved != (app.selected, app.content_offset()) {
+                save_position(book, &app);
+                saved = (app.selected, app.content_offset());
+            }
+            last_save = Instant::now();
+        }
+
+        // 组合键等待超时后执行已经完整的较短组合, 例如只绑定了 g 和 gg 时的 g
+        if let Some((action, count)) = app.keymap.tick() {
+            if !dispatch(action, count, &mut terminal, book, &mut app)? {
+                break;
+            }
+        }
+
         if crossterm::event::poll(Duration::from_millis(250))? {
-            if let Event::Key(key) = crossterm::event::read()? {
-                if key.kind == event::KeyEventKind::Press {
-                    match key.code {
-                        KeyCode::Char('j') => {
-                            if !app.focus_content {
-                                if book.selected < book.flat_toc.len() - 1 {
-                                    book.selected += 1;
-                                }
-
-                                app.outline_down();
-                                book.read_and_show_text();
-                            } else {
-                                if app.content_vertical_scroll + 10 < book.context.lines().count(){
-                                    app.content_down();
-                                }
-                            }
-                        }
+            match crossterm::event::read()? {
+                Event::Resize(_, _) => {
+                    terminal.autoresize()?;
+                    // 旧的图片位置已经无效, 整个屏幕重新绘制
+                    app.images.clear(terminal.backend_mut())?;
+                    terminal.clear()?;
+                    // 按新的大小重新折行后再限制滚动位置
+                    draw(&mut terminal, book, &mut app)?;
+                    app.clamp_content_scroll();
+                }
+                Event::Key(key) if key.kind == event::KeyEventKind::Press => {
+                    app.message = None;
 
-                        KeyCode::Char('k') => {
-                            if !app.focus_content {
-                                if book.selected > 0 {
-                                    book.selected -= 1;
-                                }
-                                app.outline_up();
-                                book.read_and_show_text();
-                            } else {
-                                app.content_up();
-                            }
-                        }
-                        KeyCode::Char('l') | KeyCode::Right => {
-                            app.focus_content = true;
-                            app.reset_content_scroll();
-                        }
-                        KeyCode::Char('h') | KeyCode::Left => {
-                            app.focus_content = false;
-                        }
-                        // 拦截g开头的按键
-                        KeyCode::Char('g') => {
-                            match crossterm::event::read()? {
-                                Event::Key(key) => match key.code {
-                                    KeyCode::Char('g') => {
-                                        app.go_top(book);
-                                    }
-                                    _ => {
-                                        // println!("key: {:?}", key);
-                                    }
-                                },
-                                _ => {}
-                            }
-                        }
-                        KeyCode::Char('G') => {
-                            // TODO: go to bottom
-                        }
-                        KeyCode::Char('q') => break,
-                        KeyCode::Char('r') => {
-                            book.read_and_show_text();
-                        }
-                        KeyCode::Char(c) if c.is_ascii_digit() => {
-                            chapter_input.push(c);
-                            // println!("Current input: {}", chapter_input);
-                        }
-                        KeyCode::Enter => {
-                            if let Ok(selected_chapter) = chapter_input.parse::<usize>() {
-                                // 确保 selected_chapter 在有效范围内
-                                if selected_chapter > 0 && selected_chapter <= book.toc.len() {
-                                    book.selected = selected_chapter - 1;
-                                    book.read_and_show_text();
-                                }
-                            } else {
-                                // println!("Invalid chapter number");
-                            }
-                            chapter_input.clear();
-                        }
-                        KeyCode::Backspace => {
-                            // 允许用户删除输入
-                            chapter_input.pop();
-                            // println!("Current input: {}", chapter_input);
-                        }
-                        _ => {
-                            // println!("key: {:?}", key);
+                    let mode = match app.visual.is_some() {
+                        true => KeyMode::Visual,
+                        false => KeyMode::Normal,
+                    };
+
+                    // 输入文字时不使用按键绑定
+                    if app.show_info {
+                        app.show_info = false;
+                    } else if app.input_mode == InputMode::Search {
+                        handle_search_input(key.code, book, &mut app);
+                    } else if app.input_mode == InputMode::BookmarkName {
+                        handle_bookmark_input(key.code, book, &mut app);
+                    } else if app.input_mode == InputMode::HighlightNote {
+                        handle_highlight_input(key.code, book, &mut app);
+                    } else if let Some((action, count)) = app.keymap.feed(key, mode) {
+                        if !dispatch(action, count, &mut terminal, book, &mut app)? {
+                            break;
                         }
                     }
                 }
+                _ => {}
             }
         }
 
+        draw(&mut terminal, book, &mut app)?;
+    }
+
+    app.images.clear(terminal.backend_mut())?;
+    Ok(())
+}
+
+fn draw(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    book: &Book,
+    app: &mut App,
+) -> io::Result<()> {
+    terminal.draw(|f| {
+        render(f, book, app);
+    })?;
+
+    // 移动 sixel 图片前先重绘整个屏幕, 清除旧的图片
+    if app.images.needs_repaint() {
+        terminal.clear()?;
         terminal.draw(|f| {
-            render(f, &book, &mut app);
+            render(f, book, app);
         })?;
     }
-    Ok(())
+
+    app.images.flush(book, terminal.backend_mut())
+}
+
+// Stop the reader until the shell continues it, then repaint everything
+fn suspend(terminal: &mut Terminal<CrosstermBackend<Stdout>>, app: &mut App) -> io::Result<()> {
+    app.images.clear(terminal.backend_mut())?;
+    terminal::suspend()?;
+    terminal.clear()
+}
+
+// Run an action in the window on top: the note, the selection or a list, otherwise in the
+// normal mode. Returns false when the reader quits
+fn dispatch(
+    action: Action,
+    count: Option<usize>,
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    book: &Book,
+    app: &mut App,
+) -> io::Result<bool> {
+    if app.note.is_some() {
+        handle_note(action, app);
+    } else if app.visual.is_some() {
+        handle_visual(action, count, app);
+    } else if app.show_search_results {
+        handle_search_results(action, book, app);
+    } else if app.show_bookmarks {
+        handle_bookmark_list(action, book, app);
+    } else if app.show_highlights {
+        handle_highlight_list(action, book, app);
+    } else {
+        match action {
+            Action::Quit => {
+                save_position(book, app);
+                return Ok(false);
+            }
+            Action::Suspend => suspend(terminal, app)?,
+            _ => run_action(action, count, book, app),
+        }
+    }
+
+    Ok(true)
+}
+
+// Save the current chapter and text offset into the index file of the book
+fn save_position(book: &Book, app: &App) {
+    // 没有缓存目录时无法保存
+    if app.cache_dir.as_os_str().is_empty() {
+        return;
+    }
+
+    let explorer = Explorer {
+        selected: app.selected,
+        mode: app.mode,
+        spine_index: app.spine_index,
+        position: app.content_offset(),
+        cfi: app
+            .current_cfi(book, app.content_offset())
+            .unwrap_or_default(),
+        ruby: app.ruby,
+        book: book.clone(),
+    };
+    // 保存失败不影响阅读
+    let _ = explorer.save(&app.cache_dir);
+}
+
+// Run an action of the normal mode, `count` is the number typed before the keys
+fn run_action(action: Action, count: Option<usize>, book: &Book, app: &mut App) {
+    let times = count.unwrap_or(1).max(1);
+    let page = app.content_height.max(1);
+    let paging = app.paginated && app.focus_content;
+
+    match action {
+        Action::ScrollDown | Action::HalfPageDown | Action::PageDown | Action::NextPage
+            if paging =>
+        {
+            (0..times).for_each(|_| next_page(book, app))
+        }
+        Action::ScrollUp | Action::HalfPageUp | Action::PageUp | Action::PrevPage if paging => {
+            (0..times).for_each(|_| prev_page(book, app))
+        }
+        Action::ScrollDown if !app.focus_content => move_outline(book, app, times as isize),
+        Action::ScrollUp if !app.focus_content => move_outline(book, app, -(times as isize)),
+        Action::ScrollDown => scroll_down(book, app, times),
+        Action::ScrollUp => scroll_up(book, app, times),
+        Action::HalfPageDown => scroll_down(book, app, (page / 2).max(1) * times),
+        Action::HalfPageUp => scroll_up(book, app, (page / 2).max(1) * times),
+        Action::PageDown => scroll_down(book, app, page * times),
+        Action::PageUp => scroll_up(book, app, page * times),
+        Action::GoTop => app.go_top(book),
+        Action::GoBottom if !app.focus_content => {
+            let last = book.flat_toc().len().saturating_sub(1);
+            move_outline(book, app, last as isize - app.selected as isize);
+        }
+        Action::GoBottom if paging => app.scroll_content_to_page(usize::MAX),
+        Action::GoBottom => {
+            let lines = app.content_lines.len();
+            app.scroll_content_to(lines.saturating_sub(app.content_height));
+        }
+        Action::NextChapter | Action::PrevChapter => {
+            let forward = action == Action::NextChapter;
+            match app.mode {
+                ReadingMode::Toc => {
+                    let step = times as isize;
+                    move_outline(book, app, if forward { step } else { -step });
+                }
+                ReadingMode::Spine => {
+                    for _ in 0..times {
+                        let moved = match forward {
+                            true => app.next_document(book),
+                            false => app.prev_document(book),
+                        };
+                        if !moved {
+                            break;
+                        }
+                    }
+                    app.scroll_outline_to(app.selected);
+                    app.reset_content_scroll();
+                }
+            }
+        }
+        Action::FocusContent | Action::NextPage => {
+            // 保留跳转到的锚点位置和恢复的阅读位置
+            app.focus_content = true;
+        }
+        Action::FocusOutline | Action::PrevPage => {
+            app.focus_content = false;
+            app.show_outline = true;
+        }
+        Action::ToggleOutline => {
+            // 隐藏大纲时只能阅读内容
+            app.show_outline = !app.show_outline;
+            app.focus_content |= !app.show_outline;
+        }
+        Action::TogglePages => {
+            app.paginated = !app.paginated;
+            let lang = app.config.language;
+            app.message = Some(match app.paginated {
+                true => lang.text("分页阅读", "Paginated").to_string(),
+                false => lang.text("滚动阅读", "Scrolling").to_string(),
+            });
+        }
+        Action::ToggleRuby => {
+            let mode = app.config.content.ruby.next();
+            app.config.content.ruby = mode;
+            app.ruby = Some(mode);
+            app.relayout(book);
+            let lang = app.config.language;
+            app.message = Some(
+                match mode {
+                    RubyMode::Above => lang.text("注音: 上方", "Ruby: above"),
+                    RubyMode::Inline => lang.text("注音: 括号", "Ruby: inline"),
+                    RubyMode::Hidden => lang.text("注音: 隐藏", "Ruby: hidden"),
+                }
+                .to_string(),
+            );
+        }
+        Action::ToggleMode => {
+            // 切换大纲阅读 / spine 连续阅读
+            app.toggle_mode();
+            app.read_and_show_text(book);
+            app.scroll_content_to_line(book, app.anchor_line);
+        }
+        Action::Reload => app.read_and_show_text(book),
+        Action::Search => {
+            app.input_mode = InputMode::Search;
+            app.search_input.clear();
+        }
+        Action::SearchNext => (0..times).for_each(|_| app.search_next(book)),
+        Action::SearchPrev => (0..times).for_each(|_| app.search_prev(book)),
+        Action::ClearSearch => app.clear_search(),
+        Action::Info => app.show_info = true,
+        Action::Back => (0..times).for_each(|_| app.go_back(book)),
+        Action::Forward => (0..times).for_each(|_| app.go_forward(book)),
+        Action::NextLink => (0..times).for_each(|_| app.cycle_link(true)),
+        Action::PrevLink => (0..times).for_each(|_| app.cycle_link(false)),
+        Action::OpenLink => match count {
+            // 数字加 Enter 跳转到对应的章节
+            Some(chapter) => {
+                if chapter > 0 && chapter <= book.toc().len() {
+                    app.selected = chapter - 1;
+                    app.read_and_show_text(book);
+                }
+            }
+            None => app.activate_link(book),
+        },
+        Action::CopyLink => app.copy_link(),
+        Action::Visual => app.start_visual(),
+        Action::AddBookmark => {
+            app.input_mode = InputMode::BookmarkName;
+            app.bookmark_input.clear();
+        }
+        Action::Bookmarks => {
+            app.show_bookmarks = true;
+            app.bookmark_selected = app
+                .bookmark_selected
+                .min(app.bookmarks.len().saturating_sub(1));
+        }
+        Action::Highlights => {
+            app.show_highlights = true;
+            app.highlight_selected = app
+                .highlight_selected
+                .min(app.highlights.len().saturating_sub(1));
+        }
+        // 需要终端或者退出前保存位置, 在 dispatch 中处理
+        Action::Suspend | Action::Quit => {}
+        // 只在列表和选择模式中使用
+        Action::Delete
+        | Action::ExportMarkdown
+        | Action::ExportJson
+        | Action::CursorLeft
+        | Action::CursorRight
+        | Action::CursorUp
+        | Action::CursorDown
+        | Action::WordForward
+        | Action::WordBackward
+        | Action::LineStart
+        | Action::LineEnd => {}
+    }
+}
+
+// Move the outline selection by `step` entries and show that chapter
+fn move_outline(book: &Book, app: &mut App, step: isize) {
+    let last = book.flat_toc().len().saturating_sub(1);
+    app.selected = app.selected.saturating_add_signed(step).min(last);
+
+    app.scroll_outline_to(app.selected);
+    app.reset_content_scroll();
+    app.read_and_show_text(book);
+    app.scroll_content_to_line(book, app.anchor_line);
+}
+
+// Turn to the next page, from the last page into the next chapter or spine document
+fn next_page(book: &Book, app: &mut App) {
+    let (page, pages) = app.page();
+    if page < pages {
+        app.scroll_content_to_page(page + 1);
+        return;
+    }
+
+    match app.mode {
+        ReadingMode::Toc if app.selected + 1 < book.flat_toc().len() => move_outline(book, app, 1),
+        ReadingMode::Toc => {}
+        ReadingMode::Spine => {
+            if app.next_document(book) {
+                app.reset_content_scroll();
+            }
+        }
+    }
+}
+
+// Turn to the previous page, from the first page to the last page of the previous chapter
+fn prev_page(book: &Book, app: &mut App) {
+    let (page, _) = app.page();
+    if page > 1 {
+        app.scroll_content_to_page(page - 1);
+        return;
+    }
+
+    let moved = match app.mode {
+        ReadingMode::Toc if app.selected > 0 => {
+            move_outline(book, app, -1);
+            true
+        }
+        ReadingMode::Spine => app.prev_document(book),
+        _ => false,
+    };
+    if moved {
+        app.layout(book, app.content_width());
+        app.scroll_content_to_page(usize::MAX);
+    }
+}
+
+fn scroll_down(book: &Book, app: &mut App, rows: usize) {
+    for _ in 0..rows {
+        // 最后一行已经显示时不再滚动
+        if app.content_vertical_scroll + app.content_height < app.content_lines.len() {
+            app.content_down();
+        } else {
+            if app.mode == ReadingMode::Spine && app.next_document(book) {
+                // 连续阅读: 滚动到文档末尾后进入下一个 spine 文档
+                app.reset_content_scroll();
+            }
+            break;
+        }
+    }
+}
+
+fn scroll_up(book: &Book, app: &mut App, rows: usize) {
+    for _ in 0..rows {
+        if app.content_vertical_scroll > 0 {
+            app.content_up();
+        } else {
+            if app.mode == ReadingMode::Spine && app.prev_document(book) {
+                app.layout(book, app.content_width());
+                let lines = app.content_lines.len();
+                app.scroll_content_to(lines.saturating_sub(app.content_height));
+            }
+            break;
+        }
+    }
+}
+
+fn handle_search_input(code: KeyCode, book: &Book, app: &mut App) {
+    match code {
+        KeyCode::Esc => app.input_mode = InputMode::Normal,
+        KeyCode::Enter => {
+            app.input_mode = InputMode::Normal;
+            app.search(book);
+        }
+        KeyCode::Backspace => {
+            app.search_input.pop();
+        }
+        KeyCode::Char(c) => app.search_input.push(c),
+        _ => {}
+    }
+}
+
+// 书签名称, 直接按 Enter 使用章节标题
+fn handle_bookmark_input(code: KeyCode, book: &Book, app: &mut App) {
+    match code {
+        KeyCode::Esc => app.input_mode = InputMode::Normal,
+        KeyCode::Enter => {
+            app.input_mode = InputMode::Normal;
+            app.add_bookmark(book);
+        }
+        KeyCode::Backspace => {
+            app.bookmark_input.pop();
+        }
+        KeyCode::Char(c) => app.bookmark_input.push(c),
+        _ => {}
+    }
+}
+
+// 书签列表: j/k 选择, Enter 跳转, d 删除
+fn handle_bookmark_list(action: Action, book: &Book, app: &mut App) {
+    match action {
+        Action::ScrollDown if app.bookmark_selected + 1 < app.bookmarks.len() => {
+            app.bookmark_selected += 1;
+        }
+        Action::ScrollUp => {
+            app.bookmark_selected = app.bookmark_selected.saturating_sub(1);
+        }
+        Action::OpenLink => {
+            app.show_bookmarks = false;
+            app.jump_to_bookmark(book, app.bookmark_selected);
+        }
+        Action::Delete => app.delete_bookmark(),
+        Action::ClearSearch | Action::Quit | Action::Bookmarks => app.show_bookmarks = false,
+        _ => {}
+    }
+}
+
+// 选择模式: 移动光标扩展选择, Enter 保存为高亮, y 复制
+fn handle_visual(action: Action, count: Option<usize>, app: &mut App) {
+    let motion = match action {
+        Action::CursorLeft => Motion::Left,
+        Action::CursorRight => Motion::Right,
+        Action::CursorUp => Motion::Up,
+        Action::CursorDown => Motion::Down,
+        Action::WordForward => Motion::WordForward,
+        Action::WordBackward => Motion::WordBackward,
+        Action::LineStart => Motion::LineStart,
+        Action::LineEnd => Motion::LineEnd,
+        Action::OpenLink => {
+            app.input_mode = InputMode::HighlightNote;
+            app.highlight_input.clear();
+            return;
+        }
+        Action::CopyLink => {
+            app.copy_selection();
+            return;
+        }
+        Action::ClearSearch | Action::Visual | Action::Quit => {
+            app.visual = None;
+            return;
+        }
+        _ => return,
+    };
+
+    for _ in 0..count.unwrap_or(1).max(1) {
+        app.move_cursor(motion);
+    }
+}
+
+// 高亮的注释, 可以为空; Esc 回到选择模式
+fn handle_highlight_input(code: KeyCode, book: &Book, app: &mut App) {
+    match code {
+        KeyCode::Esc => app.input_mode = InputMode::Normal,
+        KeyCode::Enter => {
+            app.input_mode = InputMode::Normal;
+            app.add_highlight(book);
+        }
+        KeyCode::Backspace => {
+            app.highlight_input.pop();
+        }
+        KeyCode::Char(c) => app.highlight_input.push(c),
+        _ => {}
+    }
+}
+
+// 高亮列表: j/k 选择, Enter 跳转, d 删除, e / E 导出 Markdown / JSON
+fn handle_highlight_list(action: Action, book: &Book, app: &mut App) {
+    match action {
+        Action::ScrollDown if app.highlight_selected + 1 < app.highlights.len() => {
+            app.highlight_selected += 1;
+        }
+        Action::ScrollUp => {
+            app.highlight_selected = app.highlight_selected.saturating_sub(1);
+        }
+        Action::OpenLink => {
+            app.show_highlights = false;
+            app.jump_to_highlight(book, app.highlight_selected);
+        }
+        Action::Delete => app.delete_highlight(),
+        Action::ExportMarkdown => app.export_highlights(book, false),
+        Action::ExportJson => app.export_highlights(book, true),
+        Action::ClearSearch | Action::Quit | Action::Highlights => app.show_highlights = false,
+        _ => {}
+    }
+}
+
+// 注释窗口: j/k 滚动, Esc/q/Enter 关闭
+fn handle_note(action: Action, app: &mut App) {
+    match action {
+        Action::ScrollDown => app.note_scroll += 1,
+        Action::ScrollUp => {
+            app.note_scroll = app.note_scroll.saturating_sub(1);
+        }
+        Action::ClearSearch | Action::Quit | Action::OpenLink => app.note = None,
+        _ => {}
+    }
+}
+
+// 搜索结果列表: j/k 选择, Enter 跳转
+fn handle_search_results(action: Action, book: &Book, app: &mut App) {
+    match action {
+        Action::ScrollDown if app.search_selected + 1 < app.search_hits.len() => {
+            app.search_selected += 1;
+        }
+        Action::ScrollUp => {
+            app.search_selected = app.search_selected.saturating_sub(1);
+        }
+        Action::OpenLink => {
+            app.show_search_results = false;
+            app.jump_to_hit(book, app.search_selected);
+        }
+        Action::ClearSearch | Action::Quit => app.show_search_results = false,
+        _ => {}
+    }
 }
@@ -0,0 +1,493 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::io::{self, Cursor, Write};
+
+use crossterm::{cursor::MoveTo, queue, terminal::window_size};
+use epub_parser::book::Book;
+use image::{imageops::FilterType, io::Reader, DynamicImage, Rgba, RgbaImage};
+use ratatui::style::{Color, Style};
+use ratatui::text::Span;
+
+// 无法获取单元格的像素大小时使用的默认值
+const DEFAULT_CELL: (u32, u32) = (8, 16);
+// kitty 协议每段传输的 base64 长度
+const KITTY_CHUNK: usize = 4096;
+// 可以解码的图片类型
+const RASTER_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/jpg", "image/gif"];
+// 最多保留的缩放后图片, 超出时丢弃最久没有使用的
+const SCALED_LIMIT: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    // 每个单元格用 ▀ 显示上下两个像素, 所有支持真彩色的终端都可以使用
+    HalfBlock,
+    Kitty,
+    Sixel,
+}
+
+impl Protocol {
+    // Guess the graphics support of the terminal from the environment, EPUB_READER_IMAGES
+    // forces one of kitty / sixel / halfblock
+    pub fn detect() -> Self {
+        match env::var("EPUB_READER_IMAGES").as_deref() {
+            Ok("kitty") => return Protocol::Kitty,
+            Ok("sixel") => return Protocol::Sixel,
+            Ok("halfblock") => return Protocol::HalfBlock,
+            _ => {}
+        }
+
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+
+        if env::var("KITTY_WINDOW_ID").is_ok()
+            || term.contains("kitty")
+            || matches!(program.as_str(), "WezTerm" | "ghostty")
+        {
+            Protocol::Kitty
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || program == "mlterm"
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::HalfBlock
+        }
+    }
+}
+
+// 图片在屏幕上的位置, 只用于 kitty 和 sixel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub href: String,
+    pub x: u16,
+    pub y: u16,
+    pub cols: usize,
+    // 可见的部分: 从图片的第 skip 行开始的 rows 行
+    pub skip: usize,
+    pub rows: usize,
+}
+
+// Decoded images of the book and what has been drawn on the terminal
+pub struct Images {
+    protocol: Protocol,
+    // 单元格的像素大小
+    cell: (u32, u32),
+    // 图片的像素大小, 只读取文件头; 无法解码的图片为 None
+    dimensions: HashMap<String, Option<(u32, u32)>>,
+    // 按显示宽度缩放后的图片, 最近使用的在最后; 原图解码后不保留
+    scaled: VecDeque<((String, usize), RgbaImage)>,
+    // 已经传输给 kitty 的图片
+    kitty_ids: HashMap<(String, usize), u32>,
+    // 本次绘制的图片位置和终端上已有的图片位置
+    pending: Vec<Placement>,
+    placements: Vec<Placement>,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Images::new(Protocol::detect())
+    }
+}
+
+impl Images {
+    pub fn new(protocol: Protocol) -> Self {
+        let cell = match window_size() {
+            Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => (
+                (size.width / size.columns) as u32,
+                (size.height / size.rows) as u32,
+            ),
+            _ => DEFAULT_CELL,
+        };
+
+        Images {
+            protocol,
+            cell,
+            dimensions: HashMap::new(),
+            scaled: VecDeque::new(),
+            kitty_ids: HashMap::new(),
+            pending: vec![],
+            placements: vec![],
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    // Size in cells of an image drawn at most `width` columns wide, None when it can not be
+    // decoded. Small images are not enlarged.
+    pub fn size(&mut self, book: &Book, href: &str, width: usize) -> Option<(usize, usize)> {
+        let (cell_width, cell_height) = self.pixels_per_cell();
+        let dimensions = self.dimensions(book, href)?;
+
+        let cols = (dimensions.0.div_ceil(cell_width) as usize).clamp(1, width.max(1));
+        let height = scaled_height(dimensions, cols as u32 * cell_width);
+
+        Some((cols, height.div_ceil(cell_height).max(1) as usize))
+    }
+
+    // Size in cells of an image that fits into `width` x `height` cells
+    pub fn fit(
+        &mut self,
+        book: &Book,
+        href: &str,
+        width: usize,
+        height: usize,
+    ) -> Option<(usize, usize)> {
+        let (cols, rows) = self.size(book, href, width)?;
+        if rows <= height {
+            return Some((cols, rows));
+        }
+
+        // 按高度等比缩小, 取整后可能仍然多出一行
+        let (cols, rows) = self.size(book, href, (cols * height / rows).max(1))?;
+        Some((cols, rows.min(height.max(1))))
+    }
+
+    // One row of cells of a half-block image
+    pub fn half_block_row(
+        &mut self,
+        book: &Book,
+        href: &str,
+        cols: usize,
+        row: usize,
+    ) -> Vec<Span<'static>> {
+        let Some(image) = self.scaled(book, href, cols) else {
+            return vec![];
+        };
+
+        let y = row as u32 * 2;
+        (0..image.width())
+            .map(|x| {
+                let top = pixel(image, x, y);
+                let bottom = pixel(image, x, y + 1);
+
+                // 透明的像素显示终端背景色
+                match (top, bottom) {
+                    (Some(top), Some(bottom)) => {
+                        Span::styled("▀", Style::default().fg(top).bg(bottom))
+                    }
+                    (Some(top), None) => Span::styled("▀", Style::default().fg(top)),
+                    (None, Some(bottom)) => Span::styled("▄", Style::default().fg(bottom)),
+                    (None, None) => Span::raw(" "),
+                }
+            })
+            .collect()
+    }
+
+    pub fn start_frame(&mut self) {
+        self.pending.clear();
+    }
+
+    // Remember where an image is drawn in this frame, see `flush`
+    pub fn place(&mut self, placement: Placement) {
+        self.pending.push(placement);
+    }
+
+    // Sixel images stay on the screen until the cells are written again, so the whole screen
+    // has to be repainted before they move
+    pub fn needs_repaint(&self) -> bool {
+        self.protocol == Protocol::Sixel
+            && self.pending != self.placements
+            && !self.placements.is_empty()
+    }
+
+    // Draw the images placed in this frame on top of the text, nothing happens when they did
+    // not move
+    pub fn flush(&mut self, book: &Book, out: &mut impl Write) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if pending == self.placements {
+            return Ok(());
+        }
+
+        if self.protocol == Protocol::Kitty {
+            // 删除所有显示中的图片, 保留已经传输的数据
+            write!(out, "\x1b_Ga=d,d=a,q=2\x1b\\")?;
+        }
+
+        for placement in &pending {
+            match self.protocol {
+                Protocol::Kitty => self.draw_kitty(book, out, placement)?,
+                Protocol::Sixel => self.draw_sixel(book, out, placement)?,
+                Protocol::HalfBlock => {}
+            }
+        }
+
+        self.placements = pending;
+        out.flush()
+    }
+
+    // Remove the kitty images from the terminal before leaving
+    pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.protocol == Protocol::Kitty {
+            write!(out, "\x1b_Ga=d,d=A,q=2\x1b\\")?;
+            out.flush()?;
+        }
+        self.placements.clear();
+        self.kitty_ids.clear();
+
+        Ok(())
+    }
+
+    fn pixels_per_cell(&self) -> (u32, u32) {
+        match self.protocol {
+            Protocol::HalfBlock => (1, 2),
+            _ => self.cell,
+        }
+    }
+
+    fn dimensions(&mut self, book: &Book, href: &str) -> Option<(u32, u32)> {
+        *self.dimensions.entry(href.to_string()).or_insert_with(|| {
+            let bytes = raster_bytes(book, href)?;
+            Reader::new(Cursor::new(bytes))
+                .with_guessed_format()
+                .ok()?
+                .into_dimensions()
+                .ok()
+        })
+    }
+
+    fn scaled(&mut self, book: &Book, href: &str, cols: usize) -> Option<&RgbaImage> {
+        let key = (href.to_string(), cols);
+
+        match self.scaled.iter().position(|(other, _)| *other == key) {
+            Some(index) => {
+                let entry = self.scaled.remove(index)?;
+                self.scaled.push_back(entry);
+            }
+            None => {
+                let width = cols as u32 * self.pixels_per_cell().0;
+                let Some(image) = decode(book, href) else {
+                    // 文件头正常但无法解码, 之后不再显示
+                    self.dimensions.insert(href.to_string(), None);
+                    return None;
+                };
+                let height = scaled_height((image.width(), image.height()), width).max(1);
+
+                let scaled = image
+                    .resize_exact(width, height, FilterType::Triangle)
+                    .to_rgba8();
+                if self.scaled.len() >= SCALED_LIMIT {
+                    self.scaled.pop_front();
+                }
+                self.scaled.push_back((key, scaled));
+            }
+        }
+
+        self.scaled.back().map(|(_, image)| image)
+    }
+
+    fn draw_kitty(
+        &mut self,
+        book: &Book,
+        out: &mut impl Write,
+        placement: &Placement,
+    ) -> io::Result<()> {
+        let key = (placement.href.clone(), placement.cols);
+        let (_, cell_height) = self.cell;
+
+        let id = match self.kitty_ids.get(&key) {
+            Some(id) => *id,
+            None => {
+                let id = self.kitty_ids.len() as u32 + 1;
+                let Some(image) = self.scaled(book, &placement.href, placement.cols) else {
+                    return Ok(());
+                };
+
+                // 传输 RGBA 原始数据, 之后按 id 放置
+                let data = base64(image.as_raw());
+                let (width, height) = image.dimensions();
+                let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+                for (index, chunk) in chunks.iter().enumerate() {
+                    let more = (index + 1 < chunks.len()) as u8;
+                    if index == 0 {
+                        write!(
+                            out,
+                            "\x1b_Ga=t,f=32,s={},v={},i={},q=2,m={};",
+                            width, height, id, more
+                        )?;
+                    } else {
+                        write!(out, "\x1b_Gm={};", more)?;
+                    }
+                    out.write_all(chunk)?;
+                    write!(out, "\x1b\\")?;
+                }
+
+                self.kitty_ids.insert(key, id);
+                id
+            }
+        };
+
+        // 终端保留了传输的数据, 这里只需要图片的大小
+        let Some(image) = self.scaled(book, &placement.href, placement.cols) else {
+            return Ok(());
+        };
+
+        // 只显示可见的部分
+        let y = (placement.skip as u32 * cell_height).min(image.height());
+        let height = (placement.rows as u32 * cell_height).min(image.height() - y);
+
+        queue!(out, MoveTo(placement.x, placement.y))?;
+        write!(
+            out,
+            "\x1b_Ga=p,i={},x=0,y={},w={},h={},c={},r={},C=1,q=2\x1b\\",
+            id,
+            y,
+            image.width(),
+            height,
+            placement.cols,
+            placement.rows
+        )
+    }
+
+    fn draw_sixel(
+        &mut self,
+        book: &Book,
+        out: &mut impl Write,
+        placement: &Placement,
+    ) -> io::Result<()> {
+        let (_, cell_height) = self.cell;
+        let Some(image) = self.scaled(book, &placement.href, placement.cols) else {
+            return Ok(());
+        };
+
+        let y = (placement.skip as u32 * cell_height).min(image.height());
+        let height = (placement.rows as u32 * cell_height).min(image.height() - y);
+        let visible = image::imageops::crop_imm(image, 0, y, image.width(), height).to_image();
+
+        queue!(out, MoveTo(placement.x, placement.y))?;
+        out.write_all(sixel(&visible).as_bytes())
+    }
+}
+
+// 只读取 manifest 中声明的位图
+fn raster_bytes(book: &Book, href: &str) -> Option<Vec<u8>> {
+    let item = book.manifest_item(href)?;
+    if !RASTER_TYPES.contains(&item.media_type.as_str()) {
+        return None;
+    }
+
+    book.read_bytes(href).ok()
+}
+
+fn decode(book: &Book, href: &str) -> Option<DynamicImage> {
+    image::load_from_memory(&raster_bytes(book, href)?).ok()
+}
+
+fn scaled_height((width, height): (u32, u32), target: u32) -> u32 {
+    (height as u64 * target as u64 / width.max(1) as u64) as u32
+}
+
+fn pixel(image: &RgbaImage, x: u32, y: u32) -> Option<Color> {
+    if y >= image.height() {
+        return None;
+    }
+
+    let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+    (a >= 128).then_some(Color::Rgb(r, g, b))
+}
+
+// 6x6x6 色板中最接近的颜色
+fn palette_index(pixel: &Rgba<u8>) -> usize {
+    let level = |value: u8| (value as usize * 6) / 256;
+    level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])
+}
+
+// Encode an image as sixel with a fixed 216 colour palette
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    // P2=1: 透明的像素保持背景色
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+
+    for index in 0..216 {
+        let percent = |level: usize| level * 100 / 5;
+        out.push_str(&format!(
+            "#{};2;{};{};{}",
+            index,
+            percent(index / 36),
+            percent(index / 6 % 6),
+            percent(index % 6)
+        ));
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+
+        let mut colors: Vec<usize> = rows
+            .clone()
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| image.get_pixel(x, y))
+            .filter(|pixel| pixel[3] >= 128)
+            .map(palette_index)
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+
+        for (color_index, color) in colors.iter().enumerate() {
+            if color_index > 0 {
+                // 回到行首, 叠加绘制下一种颜色
+                out.push('$');
+            }
+            out.push_str(&format!("#{}", color));
+
+            let mut run = (0, '?');
+            for x in 0..width {
+                let bits = rows.clone().fold(0u8, |bits, y| {
+                    let pixel = image.get_pixel(x, y);
+                    if pixel[3] >= 128 && palette_index(pixel) == *color {
+                        bits | 1 << (y - band)
+                    } else {
+                        bits
+                    }
+                });
+                let c = (63 + bits) as char;
+
+                if c == run.1 {
+                    run.0 += 1;
+                } else {
+                    push_run(&mut out, run);
+                    run = (1, c);
+                }
+            }
+            push_run(&mut out, run);
+        }
+
+        out.push('-');
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run(out: &mut String, (count, c): (usize, char)) {
+    match count {
+        0 => {}
+        1..=3 => out.push_str(&c.to_string().repeat(count)),
+        _ => out.push_str(&format!("!{}{}", count, c)),
+    }
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                out.push(TABLE[(value >> (18 - index * 6) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,593 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Commands of the reader, the names in config.toml are kebab-case, e.g. scroll-down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    ScrollDown,
+    ScrollUp,
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    // 分页阅读时翻页, 否则与 focus-content / focus-outline 相同
+    NextPage,
+    PrevPage,
+    TogglePages,
+    // 切换注音的显示方式: 上方, 括号, 隐藏
+    ToggleRuby,
+    GoTop,
+    GoBottom,
+    NextChapter,
+    PrevChapter,
+    FocusContent,
+    FocusOutline,
+    ToggleOutline,
+    ToggleMode,
+    Reload,
+    Search,
+    SearchNext,
+    SearchPrev,
+    ClearSearch,
+    Info,
+    Back,
+    Forward,
+    NextLink,
+    PrevLink,
+    // 有数字前缀时跳转到对应的章节
+    OpenLink,
+    CopyLink,
+    Visual,
+    AddBookmark,
+    Bookmarks,
+    Highlights,
+    // 挂起阅读器, 在 shell 中用 fg 恢复
+    Suspend,
+    Quit,
+    // 书签和高亮列表中删除选中的条目, 高亮列表中导出
+    Delete,
+    ExportMarkdown,
+    ExportJson,
+    // 选择模式中移动光标
+    CursorLeft,
+    CursorRight,
+    CursorUp,
+    CursorDown,
+    WordForward,
+    WordBackward,
+    LineStart,
+    LineEnd,
+}
+
+// Where a key is pressed: the visual mode has its own motions, the lists and the note window
+// use the normal bindings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMode {
+    Normal,
+    Visual,
+}
+
+// 组合键等待下一个按键的时间, 见配置中的 key_timeout
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+// 默认的按键, 配置中的按键替换同一个动作的默认按键
+const DEFAULT_KEYS: &[(Action, &[&str])] = &[
+    (Action::ScrollDown, &["j", "<Down>"]),
+    (Action::ScrollUp, &["k", "<Up>"]),
+    (Action::HalfPageDown, &["<C-d>"]),
+    (Action::HalfPageUp, &["<C-u>"]),
+    (Action::PageDown, &["<PageDown>", "<Space>", "<C-f>"]),
+    (Action::PageUp, &["<PageUp>", "<C-b>"]),
+    (Action::NextPage, &["<Right>"]),
+    (Action::PrevPage, &["<Left>"]),
+    (Action::TogglePages, &["p"]),
+    (Action::ToggleRuby, &["R"]),
+    (Action::GoTop, &["gg", "<Home>"]),
+    (Action::GoBottom, &["G", "<End>"]),
+    (Action::NextChapter, &["J"]),
+    (Action::PrevChapter, &["K"]),
+    (Action::FocusContent, &["l"]),
+    (Action::FocusOutline, &["h"]),
+    (Action::ToggleOutline, &["o"]),
+    (Action::ToggleMode, &["s"]),
+    (Action::Reload, &["r"]),
+    (Action::Search, &["/"]),
+    (Action::SearchNext, &["n"]),
+    (Action::SearchPrev, &["N"]),
+    (Action::ClearSearch, &["<Esc>"]),
+    (Action::Info, &["i"]),
+    // 多数终端中 Ctrl-I 和 Tab 相同, 也可以用 [ 和 ]
+    (Action::Back, &["<C-o>", "["]),
+    (Action::Forward, &["<C-i>", "]"]),
+    (Action::NextLink, &["<Tab>"]),
+    (Action::PrevLink, &["<S-Tab>"]),
+    (Action::OpenLink, &["<Enter>"]),
+    (Action::CopyLink, &["y"]),
+    (Action::Visual, &["v"]),
+    (Action::AddBookmark, &["b"]),
+    (Action::Bookmarks, &["B"]),
+    (Action::Highlights, &["H"]),
+    (Action::Suspend, &["<C-z>"]),
+    (Action::Quit, &["q"]),
+    (Action::Delete, &["d"]),
+    (Action::ExportMarkdown, &["e"]),
+    (Action::ExportJson, &["E"]),
+];
+
+// 选择模式中覆盖普通模式的按键, 其他按键(例如 Enter, y, Esc)仍然使用普通模式的动作
+const VISUAL_KEYS: &[(Action, &[&str])] = &[
+    (Action::CursorLeft, &["h", "<Left>"]),
+    (Action::CursorRight, &["l", "<Right>"]),
+    (Action::CursorUp, &["k", "<Up>"]),
+    (Action::CursorDown, &["j", "<Down>"]),
+    (Action::WordForward, &["w"]),
+    (Action::WordBackward, &["b"]),
+    (Action::LineStart, &["0", "<Home>"]),
+    (Action::LineEnd, &["$", "<End>"]),
+];
+
+// A key with its Ctrl / Alt modifiers, Shift is part of the character
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyPress {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+// Keys pressed one after another, e.g. `gg` or `<C-w>j`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeySequence(pub Vec<KeyPress>);
+
+// Maps key sequences to actions and keeps the keys of an unfinished sequence and the count
+// typed before it
+#[derive(Debug)]
+pub struct Keymap {
+    bindings: HashMap<KeySequence, Action>,
+    visual: HashMap<KeySequence, Action>,
+    // 正在输入的组合键所在的模式
+    mode: KeyMode,
+    timeout: Duration,
+    pending: Vec<KeyPress>,
+    count: Option<usize>,
+    // 与较短的组合同时完成的动作, 由 tick 返回
+    queued: Option<(Action, Option<usize>)>,
+    last_key: Instant,
+}
+
+impl From<KeyEvent> for KeyPress {
+    fn from(key: KeyEvent) -> Self {
+        let modifiers = key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let code = match key.code {
+            // 有些终端 Ctrl 加字母时报告大写
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            code => code,
+        };
+
+        KeyPress { code, modifiers }
+    }
+}
+
+impl FromStr for KeySequence {
+    type Err = String;
+
+    // `gg`, `<C-d>`, `<PageDown>`, `<Space>`, `<lt>` for `<`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut keys = vec![];
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            let named = match c {
+                '<' => rest.find('>').map(|end| (&rest[1..end], end + 1)),
+                _ => None,
+            };
+
+            match named {
+                Some((name, length)) => {
+                    keys.push(parse_named(name).ok_or_else(|| format!("unknown key: {}", text))?);
+                    rest = &rest[length..];
+                }
+                None => {
+                    keys.push(KeyPress {
+                        code: KeyCode::Char(c),
+                        modifiers: KeyModifiers::NONE,
+                    });
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+
+        match keys.is_empty() {
+            true => Err("empty key".to_string()),
+            false => Ok(KeySequence(keys)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeySequence {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|key| write!(f, "{}", key))
+    }
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char('<') => "lt".to_string(),
+            KeyCode::Char(c) if self.modifiers.is_empty() => return write!(f, "{}", c),
+            KeyCode::Char(c) => c.to_string(),
+            KeyCode::F(number) => format!("F{}", number),
+            code => format!("{:?}", code),
+        };
+
+        f.write_str("<")?;
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            f.write_str("C-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            f.write_str("A-")?;
+        }
+        write!(f, "{}>", name)
+    }
+}
+
+impl Keymap {
+    // The default bindings with the ones of the config, `timeout` is how long to wait for the
+    // next key of a sequence
+    pub fn new(keys: &HashMap<Action, Vec<KeySequence>>, timeout: Duration) -> Self {
+        let is_visual = |action: &Action| VISUAL_KEYS.iter().any(|(other, _)| other == action);
+        let bindings = collect_bindings(DEFAULT_KEYS, keys, |action| !is_visual(action));
+
+        let mut visual = bindings.clone();
+        visual.extend(collect_bindings(VISUAL_KEYS, keys, is_visual));
+
+        Keymap {
+            bindings,
+            visual,
+            mode: KeyMode::Normal,
+            timeout,
+            pending: vec![],
+            count: None,
+            queued: None,
+            last_key: Instant::now(),
+        }
+    }
+
+    // Feed a key press, returns the action and its count when a binding is complete. A
+    // sequence that is also the start of a longer one waits for the next key or the timeout.
+    pub fn feed(&mut self, key: KeyEvent, mode: KeyMode) -> Option<(Action, Option<usize>)> {
+        let key = KeyPress::from(key);
+        self.last_key = Instant::now();
+        // 切换模式后放弃没有完成的组合
+        if mode != self.mode {
+            self.mode = mode;
+            self.pending.clear();
+            self.count = None;
+        }
+
+        // 数字前缀, 单独的 0 可以绑定到动作
+        if let (true, KeyCode::Char(c @ '0'..='9')) = (self.pending.is_empty(), key.code) {
+            if key.modifiers.is_empty() && (c != '0' || self.count.is_some()) {
+                let digit = c as usize - '0' as usize;
+                self.count = Some(
+                    self.count
+                        .unwrap_or(0)
+                        .saturating_mul(10)
+                        .saturating_add(digit),
+                );
+                return None;
+            }
+        }
+
+        self.pending.push(key);
+        let sequence = KeySequence(self.pending.clone());
+        let exact = self.bindings().get(&sequence).copied();
+        let longer = self
+            .bindings()
+            .keys()
+            .any(|other| other.0.len() > self.pending.len() && other.0.starts_with(&self.pending));
+
+        match (exact, longer) {
+            (Some(action), false) => {
+                self.pending.clear();
+                Some((action, self.count.take()))
+            }
+            (_, true) => None,
+            (None, false) => {
+                // 没有这个组合: 前面的按键是完整的组合时先执行它(例如绑定了 g 和 gg 时的 gj),
+                // 最后一个按键重新开始, 它的动作在下一次 tick 时返回
+                let previous = KeySequence(self.pending[..self.pending.len() - 1].to_vec());
+                let retry = self.pending.len() > 1;
+                let shorter = self.bindings().get(&previous).copied().filter(|_| retry);
+                let count = self.count.take();
+                self.pending.clear();
+
+                let next = match retry {
+                    true => self.feed(KeyEvent::new(key.code, key.modifiers), mode),
+                    false => None,
+                };
+                match shorter {
+                    Some(action) => {
+                        self.queued = next;
+                        Some((action, count))
+                    }
+                    None => next,
+                }
+            }
+        }
+    }
+
+    // The action of an unfinished sequence once no more keys came within the timeout, or the
+    // action of a key that followed a shorter binding, see `feed`
+    pub fn tick(&mut self) -> Option<(Action, Option<usize>)> {
+        if let Some(queued) = self.queued.take() {
+            return Some(queued);
+        }
+        if self.pending.is_empty() || self.last_key.elapsed() < self.timeout {
+            return None;
+        }
+
+        let sequence = KeySequence(std::mem::take(&mut self.pending));
+        let count = self.count.take();
+        self.bindings()
+            .get(&sequence)
+            .copied()
+            .map(|action| (action, count))
+    }
+
+    // The shortest key bound to the action in `mode`, shown in the hints. "-" when the action
+    // has no key
+    pub fn key(&self, action: Action, mode: KeyMode) -> String {
+        let bindings = match mode {
+            KeyMode::Normal => &self.bindings,
+            KeyMode::Visual => &self.visual,
+        };
+
+        bindings
+            .iter()
+            .filter(|(_, other)| **other == action)
+            .map(|(sequence, _)| sequence.to_string())
+            .min_by_key(|key| (key.chars().count(), key.clone()))
+            .unwrap_or_else(|| "-".to_string())
+    }
+
+    fn bindings(&self) -> &HashMap<KeySequence, Action> {
+        match self.mode {
+            KeyMode::Normal => &self.bindings,
+            KeyMode::Visual => &self.visual,
+        }
+    }
+
+    // The count and keys typed so far, shown in the status line
+    pub fn pending(&self) -> String {
+        let count = self
+            .count
+            .map(|count| count.to_string())
+            .unwrap_or_default();
+        format!("{}{}", count, KeySequence(self.pending.clone()))
+    }
+}
+
+// The default keys of the actions in `defaults`, replaced by the keys of the config for the
+// actions `owns`
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::new(&HashMap::new(), DEFAULT_TIMEOUT)
+    }
+}
+
+fn collect_bindings(
+    defaults: &[(Action, &[&str])],
+    keys: &HashMap<Action, Vec<KeySequence>>,
+    owns: impl Fn(&Action) -> bool,
+) -> HashMap<KeySequence, Action> {
+    let mut bindings = HashMap::new();
+
+    for (action, defaults) in defaults {
+        if keys.contains_key(action) {
+            continue;
+        }
+        for key in defaults.iter().filter_map(|key| key.parse().ok()) {
+            bindings.insert(key, *action);
+        }
+    }
+    for (action, sequences) in keys.iter().filter(|(action, _)| owns(action)) {
+        for sequence in sequences {
+            bindings.insert(sequence.clone(), *action);
+        }
+    }
+
+    bindings
+}
+
+fn parse_named(name: &str) -> Option<KeyPress> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+
+    // C-, A- / M-, S- 前缀
+    while let Some((prefix, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+        match prefix.to_ascii_lowercase().as_str() {
+            "c" | "ctrl" => modifiers |= KeyModifiers::CONTROL,
+            "a" | "m" | "alt" => modifiers |= KeyModifiers::ALT,
+            "s" | "shift" => modifiers |= KeyModifiers::SHIFT,
+            _ => return None,
+        }
+        rest = key;
+    }
+
+    let code = match rest.to_ascii_lowercase().as_str() {
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "enter" | "cr" | "return" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        key if key.len() > 1 && key.starts_with('f') => KeyCode::F(key[1..].parse().ok()?),
+        _ => {
+            let mut chars = rest.chars();
+            let c = chars.next()?;
+            if chars.next().is_some() {
+                return None;
+            }
+            // Shift 加字符就是大写字符, Ctrl 加字母不区分大小写
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                KeyCode::Char(c.to_ascii_lowercase())
+            } else if modifiers.contains(KeyModifiers::SHIFT) {
+                KeyCode::Char(c.to_ascii_uppercase())
+            } else {
+                KeyCode::Char(c)
+            }
+        }
+    };
+
+    Some(KeyPress {
+        code,
+        modifiers: modifiers - KeyModifiers::SHIFT,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    fn keymap(keys: &[(Action, &str)], timeout: Duration) -> Keymap {
+        let mut config: HashMap<Action, Vec<KeySequence>> = HashMap::new();
+        for (action, key) in keys {
+            config
+                .entry(*action)
+                .or_default()
+                .push(key.parse().unwrap());
+        }
+        Keymap::new(&config, timeout)
+    }
+
+    #[test]
+    fn parse_keys() {
+        let sequence: KeySequence = "<C-d>".parse().unwrap();
+        assert_eq!(
+            sequence.0,
+            [KeyPress {
+                code: KeyCode::Char('d'),
+                modifiers: KeyModifiers::CONTROL,
+            }]
+        );
+
+        let sequence: KeySequence = "<lt>g".parse().unwrap();
+        assert_eq!(
+            sequence.0.iter().map(|key| key.code).collect::<Vec<_>>(),
+            [KeyCode::Char('<'), KeyCode::Char('g')]
+        );
+        assert_eq!(sequence.to_string(), "<lt>g");
+
+        let sequence: KeySequence = "<S-Tab>".parse().unwrap();
+        assert_eq!(sequence.0[0].code, KeyCode::BackTab);
+
+        assert!("<Nope>".parse::<KeySequence>().is_err());
+        assert!("".parse::<KeySequence>().is_err());
+    }
+
+    #[test]
+    fn counts() {
+        let mut keymap = Keymap::default();
+        let mode = KeyMode::Normal;
+
+        assert_eq!(keymap.feed(press('1'), mode), None);
+        assert_eq!(keymap.feed(press('0'), mode), None);
+        assert_eq!(keymap.pending(), "10");
+        assert_eq!(
+            keymap.feed(press('j'), mode),
+            Some((Action::ScrollDown, Some(10)))
+        );
+        assert_eq!(
+            keymap.feed(press('j'), mode),
+            Some((Action::ScrollDown, None))
+        );
+
+        // 单独的 0 是选择模式中的动作
+        assert_eq!(
+            keymap.feed(press('0'), KeyMode::Visual),
+            Some((Action::LineStart, None))
+        );
+        assert_eq!(
+            keymap.feed(press('b'), KeyMode::Visual),
+            Some((Action::WordBackward, None))
+        );
+        assert_eq!(
+            keymap.feed(press('b'), mode),
+            Some((Action::AddBookmark, None))
+        );
+    }
+
+    #[test]
+    fn prefix_conflicts() {
+        let mut keymap = keymap(
+            &[(Action::GoBottom, "g"), (Action::GoTop, "gg")],
+            Duration::from_secs(60),
+        );
+        let mode = KeyMode::Normal;
+
+        assert_eq!(keymap.feed(press('g'), mode), None);
+        assert_eq!(keymap.feed(press('g'), mode), Some((Action::GoTop, None)));
+
+        // g 后面的 j 不能组成 gj: 先执行 g, j 在下一次 tick 时返回
+        assert_eq!(keymap.feed(press('2'), mode), None);
+        assert_eq!(keymap.feed(press('g'), mode), None);
+        assert_eq!(
+            keymap.feed(press('j'), mode),
+            Some((Action::GoBottom, Some(2)))
+        );
+        assert_eq!(keymap.tick(), Some((Action::ScrollDown, None)));
+        assert_eq!(keymap.tick(), None);
+
+        // 没有绑定的组合直接放弃
+        assert_eq!(keymap.feed(press('z'), mode), None);
+        assert_eq!(keymap.pending(), "");
+    }
+
+    #[test]
+    fn timeout() {
+        let keys = [(Action::GoBottom, "g"), (Action::GoTop, "gg")];
+        let mode = KeyMode::Normal;
+
+        let mut waiting = keymap(&keys, Duration::from_secs(60));
+        assert_eq!(waiting.feed(press('g'), mode), None);
+        assert_eq!(waiting.tick(), None);
+        assert_eq!(waiting.pending(), "g");
+
+        let mut expired = keymap(&keys, Duration::ZERO);
+        assert_eq!(expired.feed(press('3'), mode), None);
+        assert_eq!(expired.feed(press('g'), mode), None);
+        assert_eq!(expired.tick(), Some((Action::GoBottom, Some(3))));
+        assert_eq!(expired.pending(), "");
+        assert_eq!(expired.tick(), None);
+    }
+}
@@ -0,0 +1,286 @@
+use std::ops::Range;
+
+use super::linebreak::{break_lines, char_width, text_width, Row};
+use crate::config::{ContentConfig, RubyMode};
+use epub_parser::styled::{LineKind, Ruby, StyledLine, StyledSpan, StyledText};
+
+// 段落首行缩进使用的全角空格
+const INDENT: &str = "\u{3000}";
+
+// 折行后的一行内容, offset 是这一行在章节文本中的字节位置
+#[derive(Debug, Default, Clone)]
+pub struct WrappedLine {
+    pub prefix: String,
+    pub spans: Vec<StyledSpan>,
+    pub kind: LineKind,
+    pub offset: usize,
+    // 第一个字符(前缀之后)在章节文本中的字节位置
+    pub text_offset: usize,
+    // 图片占据的一行
+    pub image: Option<ImageRow>,
+    // 配置了行间距时插入的空行
+    pub spacer: bool,
+    // 中文与英文之间的空格, 在这一行文本中的字节位置, 空格画在这个字符之前
+    pub gaps: Vec<usize>,
+    // 注音显示在括号中时这一行的注音, 范围是这一行文本中的字节位置
+    pub ruby: Vec<Ruby>,
+    // 注音显示在上方时文字上面的注音行, 这一行也是 spacer
+    pub annotation: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImageRow {
+    pub href: String,
+    // 图片的第几行, 图片一共占 rows 行 cols 列
+    pub row: usize,
+    pub rows: usize,
+    pub cols: usize,
+}
+
+impl WrappedLine {
+    // Text of the spans, without the prefix
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+}
+
+// Wrap the styled chapter to `width` columns, keeping the text offset of every row. The
+// offsets point into the plain text of the chapter (`StyledText::to_string`). `options` sets
+// the CJK spacing and the paragraph indent, `image_size` returns the size in cells of an
+// image at most that many columns wide.
+pub fn wrap_text(
+    text: &StyledText,
+    width: usize,
+    options: &ContentConfig,
+    mut image_size: impl FnMut(&str, usize) -> Option<(usize, usize)>,
+) -> Vec<WrappedLine> {
+    let mut rows = vec![];
+    let mut line_start = 0;
+
+    for line in &text.lines {
+        let content = line.content();
+        // 去掉前缀后可用的宽度
+        let available = width.saturating_sub(text_width(&line.prefix)).max(1);
+
+        // 图片的每一行都指向图片所在行的开头, 解码失败时显示替代文字
+        let size = match line.kind {
+            LineKind::Image if width > 0 => image_size(&line.image, available),
+            _ => None,
+        };
+        if let Some((cols, image_rows)) = size {
+            for row in 0..image_rows {
+                rows.push(WrappedLine {
+                    prefix: match row {
+                        0 => line.prefix.clone(),
+                        _ => line.indent.clone(),
+                    },
+                    kind: line.kind,
+                    offset: line_start,
+                    text_offset: line_start + line.prefix.len(),
+                    image: Some(ImageRow {
+                        href: line.image.clone(),
+                        row,
+                        rows: image_rows,
+                        cols,
+                    }),
+                    ..Default::default()
+                });
+            }
+
+            line_start += line.prefix.len() + content.len() + 1;
+            continue;
+        }
+
+        let indent = match is_paragraph(line) {
+            true => INDENT.repeat(options.indent),
+            false => String::new(),
+        };
+        // 注音对应的文字不折开, 括号中的注音也占宽度
+        let ruby: Vec<_> = match options.ruby {
+            RubyMode::Hidden => vec![],
+            mode => line
+                .ruby
+                .iter()
+                .map(|ruby| match mode {
+                    RubyMode::Inline => (ruby.range.clone(), text_width(&ruby.text) + 2),
+                    _ => (ruby.range.clone(), 0),
+                })
+                .collect(),
+        };
+        let wrapped = match line.kind {
+            LineKind::Text if !content.is_empty() && width > 0 => {
+                let first = available.saturating_sub(text_width(&indent)).max(1);
+                break_lines(&content, first, available, options.cjk_spacing, &ruby)
+            }
+            LineKind::Pre if !content.is_empty() && width > 0 => chunk_ranges(&content, available)
+                .into_iter()
+                .map(|range| Row {
+                    range,
+                    gaps: vec![],
+                })
+                .collect(),
+            _ => vec![Row {
+                range: 0..content.len(),
+                gaps: vec![],
+            }],
+        };
+
+        for (index, Row { range, gaps }) in wrapped.into_iter().enumerate() {
+            let text_offset = line_start + line.prefix.len() + range.start;
+            let (prefix, offset) = match index {
+                // 第一行从前缀开始, 这样行首的位置也能找到这一行
+                0 => (format!("{}{}", line.prefix, indent), line_start),
+                _ => (line.indent.clone(), text_offset),
+            };
+            let gaps: Vec<usize> = gaps.iter().map(|gap| gap - range.start).collect();
+            let ruby: Vec<Ruby> = match options.ruby {
+                RubyMode::Hidden => vec![],
+                _ => line
+                    .ruby
+                    .iter()
+                    .filter(|ruby| ruby.range.start >= range.start && ruby.range.end <= range.end)
+                    .map(|ruby| Ruby {
+                        range: ruby.range.start - range.start..ruby.range.end - range.start,
+                        text: ruby.text.clone(),
+                    })
+                    .collect(),
+            };
+
+            if options.ruby == RubyMode::Above && !ruby.is_empty() {
+                // 注音行的前缀保留引用的竖线, 宽度与文字行相同
+                let extra = text_width(&prefix).saturating_sub(text_width(&line.indent));
+                rows.push(WrappedLine {
+                    prefix: format!("{}{}", line.indent, " ".repeat(extra)),
+                    offset,
+                    text_offset,
+                    spacer: true,
+                    annotation: Some(annotation(&content[range.clone()], &ruby, &gaps)),
+                    ..Default::default()
+                });
+            }
+
+            let spans = match line.kind {
+                LineKind::Rule => vec![StyledSpan {
+                    text: "─".repeat(available),
+                    ..Default::default()
+                }],
+                _ => slice_spans(&line.spans, range),
+            };
+
+            rows.push(WrappedLine {
+                prefix,
+                spans,
+                kind: line.kind,
+                offset,
+                text_offset,
+                image: None,
+                spacer: false,
+                gaps,
+                ruby: match options.ruby {
+                    RubyMode::Inline => ruby,
+                    _ => vec![],
+                },
+                annotation: None,
+            });
+        }
+
+        line_start += line.prefix.len() + content.len() + 1;
+    }
+
+    rows
+}
+
+// Byte offset of the first character of a line in the text
+pub fn line_offset(text: &str, line: usize) -> usize {
+    text.split_inclusive('\n').take(line).map(str::len).sum()
+}
+
+// The row that contains the text offset, the first one when several rows share it (images)
+pub fn row_of_offset(rows: &[WrappedLine], offset: usize) -> usize {
+    let last = rows
+        .partition_point(|row| row.offset <= offset)
+        .saturating_sub(1);
+
+    match rows.get(last) {
+        Some(row) => rows.partition_point(|other| other.offset < row.offset),
+        None => 0,
+    }
+}
+
+// 预格式化文本按字符折行, 保留空白
+fn chunk_ranges(content: &str, width: usize) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut start = 0;
+    let mut used = 0;
+
+    for (index, c) in content.char_indices() {
+        let char_width = char_width(c);
+        if used + char_width > width && index > start {
+            ranges.push(start..index);
+            start = index;
+            used = 0;
+        }
+        used += char_width;
+    }
+    ranges.push(start..content.len());
+
+    ranges
+}
+
+// The parts of the spans that fall into a byte range of the line
+fn slice_spans(spans: &[StyledSpan], range: Range<usize>) -> Vec<StyledSpan> {
+    let mut sliced = vec![];
+    let mut span_start = 0;
+
+    for span in spans {
+        let span_end = span_start + span.text.len();
+        let start = range.start.max(span_start);
+        let end = range.end.min(span_end);
+
+        if start < end {
+            sliced.push(StyledSpan {
+                text: span.text[start - span_start..end - span_start].to_string(),
+                style: span.style,
+                link: span.link,
+            });
+        }
+        span_start = span_end;
+    }
+
+    sliced
+}
+
+// The annotation row above a row of text, every annotation is centred over its base and
+// pushed right when it would overlap the one before
+fn annotation(text: &str, ruby: &[Ruby], gaps: &[usize]) -> String {
+    let mut annotation = String::new();
+    let mut used = 0;
+
+    for ruby in ruby {
+        let gaps_before = gaps.iter().filter(|gap| **gap <= ruby.range.start).count();
+        let gaps_inside = gaps
+            .iter()
+            .filter(|gap| **gap > ruby.range.start && **gap < ruby.range.end)
+            .count();
+        let column = text_width(&text[..ruby.range.start]) + gaps_before;
+        let base = text_width(&text[ruby.range.clone()]) + gaps_inside;
+        let width = text_width(&ruby.text);
+
+        let column = (column + base / 2).saturating_sub(width / 2).max(used);
+        annotation.push_str(&" ".repeat(column - used));
+        annotation.push_str(&ruby.text);
+        used = column + width;
+    }
+
+    annotation
+}
+
+// 普通段落的第一行缩进, 列表项(序号和后续行的前缀不同)和标题不缩进
+fn is_paragraph(line: &StyledLine) -> bool {
+    line.kind == LineKind::Text
+        && line.prefix == line.indent
+        && line
+            .spans
+            .first()
+            .is_some_and(|span| span.style.heading == 0)
+}
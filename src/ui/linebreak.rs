@@ -0,0 +1,300 @@
+use std::ops::Range;
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+use unicode_width::UnicodeWidthChar;
+
+// 行首禁则: 不能出现在行首的标点和小写假名
+const NO_LINE_START: &str = "!%),.:;?]}¢°’”‰′″℃、。々〉》」』】〕〗〙〟ゝゞ・ヽヾーァィゥェォッャュョヮヵヶぁぃぅぇぉっゃゅょゎゕゖ！％），．：；？］｝～｡｣､･ｰ…‥—–·";
+// 行尾禁则: 不能出现在行尾的开始标点
+const NO_LINE_END: &str = "([{£¥‘“〈《「『【〔〖〘〝（［｛｢￡￥";
+
+// A row of a wrapped paragraph: the byte range of its text, and the positions where a blank
+// column separates CJK and Latin text
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Row {
+    pub range: Range<usize>,
+    pub gaps: Vec<usize>,
+}
+
+// Columns of a character by its East Asian Width, ambiguous characters are narrow like in
+// ratatui
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+pub fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+// Break a paragraph into rows at most `first` columns wide for the first row and `rest` for
+// the others. Rows end at UAX #14 break opportunities that the kinsoku rules allow, words
+// wider than a row are broken between characters. The whitespace at a break belongs to no
+// row. With `spacing`, a blank column is put between CJK and Latin text. `ruby` are the
+// ruby bases, which are kept in one row, and the columns drawn after them.
+pub fn break_lines(
+    content: &str,
+    first: usize,
+    rest: usize,
+    spacing: bool,
+    ruby: &[(Range<usize>, usize)],
+) -> Vec<Row> {
+    let gaps = match spacing {
+        true => cjk_gaps(content),
+        false => vec![],
+    };
+    let mut wrapper = Wrapper {
+        content,
+        gaps: &gaps,
+        ruby,
+        limit: first.max(1),
+        rest: rest.max(1),
+        rows: vec![],
+        start: 0,
+        end: 0,
+        width: 0,
+    };
+
+    let mut segment_start = 0;
+    for (index, _) in linebreaks(content).filter(|(index, opportunity)| {
+        *index == content.len()
+            || (*opportunity == BreakOpportunity::Allowed
+                && kinsoku_allows(content, *index)
+                && !ruby
+                    .iter()
+                    .any(|(base, _)| base.start < *index && *index < base.end))
+    }) {
+        wrapper.push_segment(segment_start..index);
+        segment_start = index;
+    }
+    wrapper.finish();
+
+    wrapper.rows
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{3134f}')
+}
+
+fn is_latin(c: char) -> bool {
+    c.is_alphanumeric() && !is_cjk(c) && char_width(c) == 1
+}
+
+// 中文与英文, 数字直接相连的位置, 空白和标点两边不加空格
+fn cjk_gaps(content: &str) -> Vec<usize> {
+    let mut gaps = vec![];
+    let mut previous = None;
+
+    for (index, c) in content.char_indices() {
+        if let Some(previous) = previous {
+            if (is_cjk(previous) && is_latin(c)) || (is_latin(previous) && is_cjk(c)) {
+                gaps.push(index);
+            }
+        }
+        previous = Some(c);
+    }
+
+    gaps
+}
+
+fn kinsoku_allows(content: &str, index: usize) -> bool {
+    let next = content[index..].chars().next();
+    let previous = content[..index].trim_end().chars().next_back();
+
+    !next.is_some_and(|c| NO_LINE_START.contains(c))
+        && !previous.is_some_and(|c| NO_LINE_END.contains(c))
+}
+
+struct Wrapper<'a> {
+    content: &'a str,
+    gaps: &'a [usize],
+    ruby: &'a [(Range<usize>, usize)],
+    // 当前行的宽度限制, 第一行减去缩进
+    limit: usize,
+    rest: usize,
+    rows: Vec<Row>,
+    // 当前行的开始, 去掉末尾空白后的结束位置和宽度
+    start: usize,
+    end: usize,
+    width: usize,
+}
+
+impl Wrapper<'_> {
+    // Columns of the text in a range with the gaps inside it and the ruby after it
+    fn width(&self, range: Range<usize>) -> usize {
+        let gaps = self
+            .gaps
+            .iter()
+            .filter(|gap| **gap > range.start && **gap < range.end)
+            .count();
+        let ruby: usize = self
+            .ruby
+            .iter()
+            .filter(|(base, _)| base.end > range.start && base.end <= range.end)
+            .map(|(_, width)| width)
+            .sum();
+        text_width(&self.content[range]) + gaps + ruby
+    }
+
+    fn push_segment(&mut self, segment: Range<usize>) {
+        let visible = segment.start + self.content[segment.clone()].trim_end().len();
+        if visible == segment.start {
+            return;
+        }
+
+        if self.end > self.start {
+            // 接在当前行后面时, 中间的空白和间隔也占宽度
+            let width = self.width(self.end..visible) + self.gap_at(self.end);
+            if self.width + width <= self.limit {
+                self.width += width;
+                self.end = visible;
+                return;
+            }
+            self.emit();
+            self.start = segment.start;
+        } else {
+            self.start = segment.start;
+        }
+
+        let width = self.width(segment.start..visible);
+        if width <= self.limit {
+            self.end = visible;
+            self.width = width;
+        } else {
+            self.break_chars(segment.start..visible);
+        }
+    }
+
+    // 放不下一行的词按字符折行, 行首禁则的字符和前一个字符一起换到下一行, 行尾禁则的字符
+    // 和后一个字符一起换到下一行
+    fn break_chars(&mut self, range: Range<usize>) {
+        self.end = range.start;
+        self.width = 0;
+
+        for (index, c) in self.content[range.clone()].char_indices() {
+            let index = range.start + index;
+
+            if self.width + self.char_columns(index, c) > self.limit && self.end > self.start {
+                let mut cut = index;
+                while cut > self.start && !kinsoku_allows(self.content, cut) {
+                    cut = self.start
+                        + self.content[self.start..cut]
+                            .char_indices()
+                            .last()
+                            .map(|(previous, _)| previous)
+                            .unwrap_or(0);
+                }
+                // 整行都是禁则的字符时不能再往前移
+                if cut == self.start {
+                    cut = index;
+                }
+
+                self.end = cut;
+                self.emit();
+                self.start = cut;
+                self.width = self.width(cut..index);
+            }
+
+            self.width += self.char_columns(index, c);
+            self.end = index + c.len_utf8();
+        }
+    }
+
+    // 字符的宽度加上它前面的间隔和后面的注音, 行首的间隔不显示
+    fn char_columns(&self, index: usize, c: char) -> usize {
+        let gap = match index > self.start {
+            true => self.gap_at(index),
+            false => 0,
+        };
+        let end = index + c.len_utf8();
+        let ruby: usize = self
+            .ruby
+            .iter()
+            .filter(|(base, _)| base.end == end)
+            .map(|(_, width)| width)
+            .sum();
+        char_width(c) + gap + ruby
+    }
+
+    fn gap_at(&self, index: usize) -> usize {
+        self.gaps.binary_search(&index).is_ok() as usize
+    }
+
+    fn emit(&mut self) {
+        let range = self.start..self.end;
+        let gaps = self
+            .gaps
+            .iter()
+            .filter(|gap| **gap > range.start && **gap < range.end)
+            .copied()
+            .collect();
+
+        self.rows.push(Row { range, gaps });
+        self.limit = self.rest;
+        self.width = 0;
+    }
+
+    fn finish(&mut self) {
+        if self.end > self.start || self.rows.is_empty() {
+            self.emit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(content: &str, width: usize, spacing: bool) -> Vec<&str> {
+        break_lines(content, width, width, spacing, &[])
+            .into_iter()
+            .map(|row| &content[row.range])
+            .collect()
+    }
+
+    #[test]
+    fn cjk_widths() {
+        assert_eq!(text_width("中文abc"), 7);
+        assert_eq!(text_width("かな、。"), 8);
+        assert_eq!(rows("中文中文中文", 4, false), ["中文", "中文", "中文"]);
+        assert_eq!(rows("中文中文中", 5, false), ["中文", "中文", "中"]);
+    }
+
+    #[test]
+    fn kinsoku_between_words() {
+        // 句号不能在行首, 和前一个字一起换行
+        assert_eq!(rows("一二三。四五", 6, false), ["一二", "三。四", "五"]);
+        // 开始的引号不能在行尾
+        assert_eq!(rows("一二「三四」", 6, false), ["一二", "「三", "四」"]);
+    }
+
+    #[test]
+    fn kinsoku_inside_long_words() {
+        // 没有折行机会的长词按字符折行, 两种禁则都适用
+        assert_eq!(rows("abcdefghi.jk", 9, false), ["abcdefgh", "i.jk"]);
+        assert_eq!(rows("abcdefgh(ijklmn", 9, false), ["abcdefgh", "(ijklmn"]);
+        assert_eq!(rows("((((((", 3, false), ["(((", "((("]);
+    }
+
+    #[test]
+    fn mixed_latin_and_cjk_spacing() {
+        let content = "中文abc中文";
+        let row = &break_lines(content, 20, 20, true, &[])[0];
+        assert_eq!(row.gaps, [6, 9]);
+
+        // 间隔也占宽度: 4 + 3 + 4 加上两个间隔是 13 列
+        assert_eq!(rows(content, 13, true), [content]);
+        assert_eq!(rows(content, 13, false), [content]);
+        assert_eq!(rows(content, 11, false), [content]);
+        assert_eq!(rows(content, 10, true), ["中文abc", "中文"]);
+        assert_eq!(rows(content, 10, false), ["中文abc中", "文"]);
+
+        // 空白和标点两边不加间隔
+        assert!(break_lines("中文 abc，中文", 20, 20, true, &[])[0]
+            .gaps
+            .is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use super::image::base64;
+
+// 只打开网页和邮件链接, file:, javascript: 等其他协议可能启动本地程序
+const OPEN_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+// Whether an external link may be opened, links with other schemes are only copied
+pub fn can_open(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        OPEN_SCHEMES
+            .iter()
+            .any(|other| scheme.eq_ignore_ascii_case(other))
+    })
+}
+
+// Open an external link with the command in EPUB_READER_OPEN, e.g. "firefox --new-tab", or
+// with the default opener of the system
+pub fn open_external(url: &str) -> io::Result<()> {
+    if !can_open(url) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only http, https and mailto links are opened",
+        ));
+    }
+
+    let command = env::var("EPUB_READER_OPEN")
+        .ok()
+        .filter(|command| !command.trim().is_empty())
+        .unwrap_or_else(|| default_opener().to_string());
+
+    let mut parts = command.split_whitespace();
+    let program = parts.next().unwrap_or_default();
+
+    let mut child = Command::new(program)
+        .args(parts)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // 回收子进程, 不阻塞界面
+    thread::spawn(move || child.wait());
+
+    Ok(())
+}
+
+// Copy text to the clipboard with OSC 52, supported by most terminal emulators
+pub fn copy(text: &str) -> io::Result<()> {
+    let mut out = io::stdout();
+    write!(out, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    out.flush()
+}
+
+fn default_opener() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    }
+}
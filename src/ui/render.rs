@@ -1,31 +1,40 @@
-use super::app::App;
-use crate::book::{Book, Toc};
+use super::app::{App, InputMode, ReadingMode};
+use super::image::{Placement, Protocol};
+use super::keymap::{Action, KeyMode};
+use super::layout::{wrap_text, WrappedLine};
+use super::linebreak::text_width;
+use crate::config::{Language, Theme};
+use crate::format::{format_age, format_size};
+use epub_parser::book::{Book, Toc};
+use epub_parser::opf::Contributor;
+use epub_parser::search::match_ranges;
+use epub_parser::styled::{LineKind, StyledText, TextStyle};
 use ratatui::{
     layout::{Constraint, Direction, Layout},
     style::{Color, Style},
     text::Span,
-    widgets::{block::Title, Block, Borders, Paragraph, Wrap},
+    widgets::{block::Title, Block, Borders, Paragraph},
     Frame,
 };
 use ratatui::{prelude::*, widgets::*};
+use std::fs;
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn render(frame: &mut Frame, book: &Book, app: &mut App) {
+    let theme = app.config.theme.clone();
+    let lang = app.config.language;
     let mut content_title = String::new();
     let mut index = 0;
 
-    let scrollbar = Scrollbar::default()
-        .orientation(ScrollbarOrientation::VerticalRight)
-        .begin_symbol(Some("↑"))
-        .end_symbol(Some("↓"));
-
     let mut outlines = vec![];
-    book.toc.iter().for_each(|item| {
+    book.toc().iter().for_each(|item| {
         let mut select_tag = ' ';
-        let mut fg = Style::default().fg(Color::LightCyan);
-        if index == book.selected {
+        let mut fg = Style::default().fg(theme.outline);
+        if index == app.selected {
             content_title = item.title.clone();
             select_tag = '*';
-            fg = get_select_fg(true);
+            fg = selected_style(&theme);
         }
 
         outlines.push(Line::from(vec![Span::styled(
@@ -36,85 +45,904 @@ pub fn render(frame: &mut Frame, book: &Book, app: &mut App) {
         index += 1;
 
         process_node(
-            &item,
+            item,
             1,
-            &book,
+            app.selected,
+            &theme,
             &mut outlines,
             &mut index,
             &mut content_title,
         )
     });
 
-    let size = frame.size();
+    let mut size = frame.size();
+
+    // 搜索时在底部显示一行状态栏, 分页阅读时一直显示, 右边是页码
+    let status = status_line(app);
+    let mut page_area = None;
+    if status.is_some() || app.paginated {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Min(0), Constraint::Length(1)])
+            .split(size);
+        size = rows[0];
+        page_area = Some(rows[1]);
+
+        if let Some(status) = status {
+            frame.render_widget(Paragraph::new(status), rows[1]);
+        }
+    }
 
+    let outline_width = match app.show_outline {
+        true => app.config.outline.width,
+        false => 0,
+    };
     let layout = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints(vec![Constraint::Min(30), Constraint::Min(0)])
+        .constraints(vec![Constraint::Length(outline_width), Constraint::Min(0)])
         .split(size);
 
     // -------- outline scroll config start --------
     let outline_line_count = outlines.len();
-    let outline_index_text = format!("[{}/{}]", outline_line_count + 1, book.selected + 1);
-    let mut outline_titile = Title::from(format!("大纲 {}", outline_index_text).white().on_gray());
+    let outline_index_text = format!("[{}/{}]", outline_line_count + 1, app.selected + 1);
+    let outline_name = lang.text("大纲", "Outline");
+    let mut outline_titile = Title::from(
+        format!("{} {}", outline_name, outline_index_text)
+            .white()
+            .on_gray(),
+    );
     if !app.focus_content {
-        outline_titile = Title::from(format!("大纲 {}", outline_index_text).gray().on_white());
+        outline_titile = Title::from(
+            format!("{} {}", outline_name, outline_index_text)
+                .gray()
+                .on_white(),
+        );
     }
     app.outline_vertical_scroll_state = app
         .outline_vertical_scroll_state
         .content_length(outline_line_count);
+    if app.show_outline {
+        render_outline(frame, app, outlines, outline_titile, layout[0]);
+    }
+    // -------- outline scroll config end --------
+    // 去掉边框和页边距后的区域
+    let inner = text_area(layout[1].inner(&Margin::new(1, 1)), app);
+    app.layout(book, inner.width as usize);
+    app.content_height = inner.height as usize;
+    if app.paginated {
+        app.snap_to_page();
+    }
+
+    // 只生成可见的行, 图片按行生成
+    let start = app.content_vertical_scroll.min(app.content_lines.len());
+    let end = (start + inner.height as usize).min(app.content_lines.len());
+    let mut content: Vec<Line> = vec![];
+    app.images.start_frame();
+    let highlights = app.highlight_ranges(book);
+    let selection = app.selection();
+
+    for (index, row) in app.content_lines[start..end].iter().enumerate() {
+        if let Some(annotation) = &row.annotation {
+            content.push(Line::from(vec![
+                Span::styled(row.prefix.as_str(), Style::default().fg(theme.prefix)),
+                Span::styled(annotation.as_str(), Style::default().fg(theme.ruby)),
+            ]));
+            continue;
+        }
+
+        let Some(image) = &row.image else {
+            let marks = row_marks(
+                row,
+                &theme,
+                &app.search_query,
+                &highlights,
+                selection.clone(),
+                app.cursor(),
+            );
+            content.push(styled_line(row, &marks, app.selected_link, &theme));
+            continue;
+        };
+
+        let mut spans = vec![Span::styled(
+            row.prefix.as_str(),
+            Style::default().fg(theme.prefix),
+        )];
+
+        if app.images.protocol() == Protocol::HalfBlock {
+            spans.extend(
+                app.images
+                    .half_block_row(book, &image.href, image.cols, image.row),
+            );
+        } else if (image.row == 0 || index == 0) && !app.has_overlay() {
+            // kitty / sixel 在文字绘制完成后输出, 这里只记录位置; 有弹出窗口时不输出, 否则会盖住它
+            app.images.place(Placement {
+                href: image.href.clone(),
+                x: inner.x + text_width(&row.prefix) as u16,
+                y: inner.y + index as u16,
+                cols: image.cols,
+                skip: image.row,
+                rows: (image.rows - image.row).min(end - start - index),
+            });
+        }
+
+        content.push(Line::from(spans));
+    }
+    // -------- content scroll config start --------
+    app.content_vertical_scroll_state = app
+        .content_vertical_scroll_state
+        .content_length(app.content_lines.len());
+
+    let mode_text = match app.mode {
+        ReadingMode::Toc => "",
+        ReadingMode::Spine => lang.text(" [连续阅读]", " [continuous]"),
+    };
+    let content_name = lang.text("内容", "Content");
+
+    let mut content_title = Title::from(format!("{}{}", content_name, mode_text).gray().on_white());
+
+    if app.focus_content && app.show_outline {
+        // 分页阅读时左箭头用来翻页
+        let mut keys = app.keymap.key(Action::FocusOutline, KeyMode::Normal);
+        if !app.paginated {
+            keys = format!(
+                "{}/{}",
+                app.keymap.key(Action::PrevPage, KeyMode::Normal),
+                keys
+            );
+        }
+        let hint = format!(
+            "[{}{}{}]",
+            lang.text("按", ""),
+            keys,
+            lang.text("回到大纲", ": back to the outline")
+        );
+        content_title = Title::from(
+            format!("{}{} {}", content_name, mode_text, hint)
+                .white()
+                .bold()
+                .on_gray(),
+        );
+    }
+
+    frame.render_widget(
+        Block::default().title(content_title).borders(Borders::ALL),
+        layout[1],
+    );
+    frame.render_widget(Paragraph::new(content), inner);
+    frame.render_stateful_widget(
+        scrollbar(),
+        layout[1],
+        &mut app.content_vertical_scroll_state,
+    );
+    // -------- content  scroll config end --------
+
+    if let (true, Some(area)) = (app.paginated, page_area) {
+        let text = page_status(book, app);
+        let width = (text_width(&text) as u16).min(area.width);
+        let area = Rect::new(area.right() - width, area.y, width, 1);
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(text.gray()), area);
+    }
+
+    if app.show_search_results {
+        render_search_results(frame, app);
+    }
+
+    if app.show_bookmarks {
+        render_bookmarks(frame, app);
+    }
+
+    if app.show_highlights {
+        render_highlights(frame, app);
+    }
+
+    if app.show_info {
+        render_info(frame, book, app);
+    }
+
+    if app.note.is_some() {
+        render_note(frame, app);
+    }
+}
+
+fn render_outline(frame: &mut Frame, app: &mut App, outlines: Vec<Line>, title: Title, area: Rect) {
     frame.render_widget(
         Paragraph::new(outlines)
             .block(
                 Block::default()
-                    .title(outline_titile)
+                    .title(title)
                     .borders(Borders::ALL)
                     .padding(Padding::new(0, 0, 0, 0)),
             )
-            .style(Style::default().fg(Color::White))
+            .style(Style::default().fg(app.config.theme.outline_child))
             .scroll((app.outline_vertical_scroll as u16, 0)),
-        layout[0],
+        area,
+    );
+    frame.render_stateful_widget(scrollbar(), area, &mut app.outline_vertical_scroll_state);
+}
+
+// The part of the content pane the text is drawn in: without the margins, and centered when
+// it is wider than the maximum width
+fn text_area(area: Rect, app: &App) -> Rect {
+    let content = &app.config.content;
+    let mut area = area.inner(&Margin::new(content.margin, 0));
+
+    if content.max_width > 0 && area.width > content.max_width {
+        area.x += (area.width - content.max_width) / 2;
+        area.width = content.max_width;
+    }
+
+    area
+}
+
+fn scrollbar() -> Scrollbar<'static> {
+    Scrollbar::default()
+        .orientation(ScrollbarOrientation::VerticalRight)
+        .begin_symbol(Some("↑"))
+        .end_symbol(Some("↓"))
+}
+
+fn status_line(app: &App) -> Option<Line<'static>> {
+    let lang = app.config.language;
+
+    if app.input_mode == InputMode::Search {
+        return Some(Line::from(format!("/{}", app.search_input)));
+    }
+
+    if app.input_mode == InputMode::BookmarkName {
+        return Some(Line::from(format!(
+            "{}: {}",
+            lang.text(
+                "书签名称 (Enter 使用章节标题)",
+                "Bookmark name (Enter for the chapter title)"
+            ),
+            app.bookmark_input
+        )));
+    }
+
+    if app.input_mode == InputMode::HighlightNote {
+        return Some(Line::from(format!(
+            "{}: {}",
+            lang.text(
+                "注释 (可以为空, Enter 保存)",
+                "Note (optional, Enter to save)"
+            ),
+            app.highlight_input
+        )));
+    }
+
+    if let Some(message) = &app.message {
+        return Some(Line::from(message.clone().gray()));
+    }
+
+    let pending = app.keymap.pending();
+    if !pending.is_empty() {
+        return Some(Line::from(pending.gray()));
+    }
+
+    if app.visual.is_some() {
+        let key = |action| app.keymap.key(action, KeyMode::Visual);
+        let motions = [
+            [
+                Action::CursorLeft,
+                Action::CursorDown,
+                Action::CursorUp,
+                Action::CursorRight,
+            ],
+            [
+                Action::WordForward,
+                Action::WordBackward,
+                Action::LineStart,
+                Action::LineEnd,
+            ],
+        ]
+        .map(|group| group.map(key).join("/"))
+        .join(" ");
+
+        return Some(Line::from(
+            format!(
+                "{} ({} {}, {}, {}, {})",
+                lang.text("-- 选择 --", "-- VISUAL --"),
+                motions,
+                lang.text("移动", "move"),
+                hint(lang, key(Action::OpenLink), "保存高亮", "highlight"),
+                hint(lang, key(Action::CopyLink), "复制", "copy"),
+                hint(lang, key(Action::ClearSearch), "取消", "cancel"),
+            )
+            .gray(),
+        ));
+    }
+
+    if app.search_query.is_empty() {
+        return None;
+    }
+
+    let text = if app.search_hits.is_empty() {
+        format!("{}: {}", lang.text("未找到", "Not found"), app.search_query)
+    } else {
+        format!(
+            "[{}/{}] {}  {}",
+            app.search_selected + 1,
+            app.search_hits.len(),
+            app.search_query,
+            hints(
+                app,
+                &[
+                    (Action::SearchNext, "下一个", "next"),
+                    (Action::SearchPrev, "上一个", "previous"),
+                    (Action::ClearSearch, "取消", "cancel"),
+                ]
+            )
+        )
+    };
+
+    Some(Line::from(text.gray()))
+}
+
+// 分页阅读时状态栏右边的页码和全书进度
+fn page_status(book: &Book, app: &App) -> String {
+    let (page, pages) = app.page();
+    let progress = app.book_progress(book) * 100.0;
+
+    match app.config.language {
+        Language::Zh => format!(" 本章第 {}/{} 页  全书 {:.1}% ", page, pages, progress),
+        Language::En => format!(
+            " page {}/{} of chapter  {:.1}% of book ",
+            page, pages, progress
+        ),
+    }
+}
+
+// Split a content line into spans, highlighting the search matches
+fn highlight_line<'a>(line: &'a str, query: &str, theme: &Theme) -> Line<'a> {
+    let mut spans = vec![];
+    let mut last = 0;
+
+    for range in match_ranges(line, query) {
+        spans.push(Span::raw(&line[last..range.start]));
+        spans.push(Span::styled(
+            &line[range.clone()],
+            Style::default().bg(theme.search).fg(Color::Black),
+        ));
+        last = range.end;
+    }
+    spans.push(Span::raw(&line[last..]));
+
+    Line::from(spans)
+}
+
+// Background marks of a content row, relative to the row text: saved highlights, the visual
+// selection and its cursor, and the search matches
+fn row_marks(
+    row: &WrappedLine,
+    theme: &Theme,
+    query: &str,
+    highlights: &[Range<usize>],
+    selection: Option<Range<usize>>,
+    cursor: Option<usize>,
+) -> Vec<(Range<usize>, Style)> {
+    let text = row.text();
+    let row_range = row.text_offset..row.text_offset + text.len();
+
+    // 章节文本中的位置转换为这一行中的位置
+    let relative = |range: &Range<usize>| {
+        let start = range.start.max(row_range.start);
+        let end = range.end.min(row_range.end);
+        (start < end).then(|| start - row.text_offset..end - row.text_offset)
+    };
+
+    let mut marks = vec![];
+    for range in highlights.iter().filter_map(relative) {
+        marks.push((range, Style::default().bg(theme.highlight).fg(Color::White)));
+    }
+    if let Some(range) = selection.as_ref().and_then(relative) {
+        marks.push((range, Style::default().bg(theme.selection).fg(Color::White)));
+    }
+    for range in match_ranges(&text, query) {
+        marks.push((range, Style::default().bg(theme.search).fg(Color::Black)));
+    }
+    if let Some(cursor) = cursor {
+        let end = text
+            .get(cursor.saturating_sub(row.text_offset)..)
+            .and_then(|rest| rest.chars().next())
+            .map(|c| cursor + c.len_utf8())
+            .unwrap_or(cursor);
+        if let Some(range) = relative(&(cursor..end)) {
+            marks.push((range, Style::default().add_modifier(Modifier::REVERSED)));
+        }
+    }
+
+    marks
+}
+
+// Map a wrapped row to a ratatui line, keeping the styles and applying the marks and the
+// selected link
+fn styled_line<'a>(
+    row: &'a WrappedLine,
+    marks: &[(Range<usize>, Style)],
+    selected_link: Option<usize>,
+    theme: &Theme,
+) -> Line<'a> {
+    let mut spans = vec![];
+
+    if !row.prefix.is_empty() {
+        spans.push(Span::styled(
+            row.prefix.as_str(),
+            Style::default().fg(theme.prefix),
+        ));
+    }
+
+    let mut span_start = 0;
+
+    for span in &row.spans {
+        let mut style = match row.kind {
+            LineKind::Rule => Style::default().fg(theme.rule),
+            _ => text_style(span.style, theme),
+        };
+        if span.link.is_some() && span.link == selected_link {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        let span_end = span_start + span.text.len();
+
+        // 按标记和中英文间隔的边界切开, 后面的标记覆盖前面的
+        let mut bounds = vec![span_start, span_end];
+        for (range, _) in marks {
+            bounds.extend(
+                [range.start, range.end]
+                    .into_iter()
+                    .filter(|bound| *bound > span_start && *bound < span_end),
+            );
+        }
+        bounds.extend(
+            row.gaps
+                .iter()
+                .chain(row.ruby.iter().map(|ruby| &ruby.range.end))
+                .filter(|bound| **bound > span_start && **bound < span_end),
+        );
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        for piece in bounds.windows(2) {
+            let (start, end) = (piece[0], piece[1]);
+            let style = marks
+                .iter()
+                .filter(|(range, _)| range.start <= start && range.end >= end)
+                .fold(style, |style, (_, mark)| style.patch(*mark));
+
+            if row.gaps.contains(&start) {
+                // 间隔两边都被标记时间隔也使用标记的背景
+                let gap_style = marks
+                    .iter()
+                    .filter(|(range, _)| range.start < start && range.end > start)
+                    .fold(Style::default(), |style, (_, mark)| style.patch(*mark));
+                spans.push(Span::styled(" ", gap_style));
+            }
+
+            spans.push(Span::styled(
+                &span.text[start - span_start..end - span_start],
+                style,
+            ));
+
+            // 括号中的注音跟在对应的文字后面
+            for ruby in row.ruby.iter().filter(|ruby| ruby.range.end == end) {
+                spans.push(Span::styled(
+                    format!("({})", ruby.text),
+                    Style::default().fg(theme.ruby),
+                ));
+            }
+        }
+        span_start = span_end;
+    }
+
+    Line::from(spans)
+}
+
+fn text_style(text_style: TextStyle, theme: &Theme) -> Style {
+    let mut style = Style::default();
+
+    if text_style.heading > 0 {
+        style = style.fg(theme.heading(text_style.heading));
+    }
+    if text_style.code {
+        style = style.bg(theme.code_bg).fg(theme.code_fg);
+    }
+    if text_style.link {
+        style = style.fg(theme.link).add_modifier(Modifier::UNDERLINED);
+    }
+    if text_style.bold {
+        style = style.add_modifier(Modifier::BOLD);
+    }
+    if text_style.italic {
+        style = style.add_modifier(Modifier::ITALIC);
+    }
+    if text_style.underline {
+        style = style.add_modifier(Modifier::UNDERLINED);
+    }
+    if text_style.strikethrough {
+        style = style.add_modifier(Modifier::CROSSED_OUT);
+    }
+
+    style
+}
+
+// 脚注弹出窗口, 内容按窗口宽度折行
+fn render_note(frame: &mut Frame, app: &mut App) {
+    let Some(note) = &app.note else {
+        return;
+    };
+
+    let area = centered_rect(70, 50, frame.size());
+    let block = Block::default()
+        .title(format!(
+            "{} {}",
+            app.config.language.text("注释", "Note"),
+            hints(
+                app,
+                &[
+                    (Action::ScrollDown, "向下滚动", "scroll down"),
+                    (Action::ScrollUp, "向上滚动", "scroll up"),
+                    (Action::ClearSearch, "关闭", "close"),
+                ]
+            )
+        ))
+        .borders(Borders::ALL);
+    let inner = block.inner(area);
+
+    let rows = wrap_text(note, inner.width as usize, &app.config.content, |_, _| None);
+    app.note_scroll = app
+        .note_scroll
+        .min(rows.len().saturating_sub(inner.height as usize));
+    let lines: Vec<Line> = rows
+        .iter()
+        .skip(app.note_scroll)
+        .map(|row| styled_line(row, &[], None, &app.config.theme))
+        .collect();
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn render_search_results(frame: &mut Frame, app: &App) {
+    let area = centered_rect(80, 60, frame.size());
+    let theme = &app.config.theme;
+
+    let items: Vec<ListItem> = app
+        .search_hits
+        .iter()
+        .map(|hit| {
+            let mut spans = vec![Span::styled(
+                format!("{}  ", hit.title),
+                Style::default().fg(theme.label),
+            )];
+            spans.extend(highlight_line(&hit.snippet, &app.search_query, theme).spans);
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+
+    let title = format!(
+        "{} [{}] {}",
+        app.config.language.text("搜索结果", "Search results"),
+        app.search_hits.len(),
+        app.search_query
     );
+    let mut state = ListState::default().with_selected(Some(app.search_selected));
+
+    frame.render_widget(Clear, area);
     frame.render_stateful_widget(
-        scrollbar.clone(),
-        layout[0],
-        &mut app.outline_vertical_scroll_state,
+        List::new(items)
+            .block(Block::default().title(title).borders(Borders::ALL))
+            .highlight_style(selected_style(theme)),
+        area,
+        &mut state,
     );
-    // -------- outline scroll config end --------
-    let content = format!("{}", &book.context);
-    // -------- content scroll config start --------
-    app.content_vertical_scroll_state = app
-        .content_vertical_scroll_state
-        .content_length(book.context.lines().count());
+}
+
+// 封面和书籍信息
+fn render_info(frame: &mut Frame, book: &Book, app: &mut App) {
+    let area = centered_rect(80, 80, frame.size());
+    let block = Block::default()
+        .title(app.config.language.text(
+            "书籍信息 (按任意键关闭)",
+            "Book info (press any key to close)",
+        ))
+        .borders(Borders::ALL);
+    let inner = block.inner(area);
 
-    let mut content_title = Title::from("内容".gray().on_white());
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
 
-    if app.focus_content {
-        content_title = Title::from("内容 [按left或者h回到大纲]".white().bold().on_gray());
+    // 封面最多占左边五分之二
+    let cover = book.cover().and_then(|cover| {
+        let width = (inner.width as usize * 2 / 5).saturating_sub(2);
+        app.images
+            .fit(book, cover, width, inner.height as usize)
+            .map(|size| (cover, size))
+    });
+
+    let mut text_area = inner;
+    if let Some((cover, (cols, rows))) = cover {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![
+                Constraint::Length(cols as u16 + 2),
+                Constraint::Min(0),
+            ])
+            .split(inner);
+        text_area = columns[1];
+
+        if app.images.protocol() == Protocol::HalfBlock {
+            let lines: Vec<Line> = (0..rows)
+                .map(|row| Line::from(app.images.half_block_row(book, cover, cols, row)))
+                .collect();
+            frame.render_widget(Paragraph::new(lines), columns[0]);
+        } else {
+            app.images.place(Placement {
+                href: cover.to_string(),
+                x: columns[0].x,
+                y: columns[0].y,
+                cols,
+                skip: 0,
+                rows,
+            });
+        }
     }
 
     frame.render_widget(
-        Paragraph::new(content)
-            .block(Block::default().title(content_title).borders(Borders::ALL))
-            .wrap(Wrap { trim: true })
-            .scroll((app.content_vertical_scroll as u16, 0)),
-        layout[1],
+        Paragraph::new(info_lines(book, app)).wrap(Wrap { trim: false }),
+        text_area,
     );
-    frame.render_stateful_widget(scrollbar, layout[1], &mut app.content_vertical_scroll_state);
-    // -------- content  scroll config end --------
 }
 
-fn get_select_fg(light: bool) -> Style {
-    if light {
-        Style::default().bg(Color::LightBlue).fg(Color::White)
-    } else {
-        Style::default().bg(Color::Blue).fg(Color::Cyan)
+fn info_lines(book: &Book, app: &App) -> Vec<Line<'static>> {
+    let metadata = book.metadata();
+    let lang = app.config.language;
+    let theme = &app.config.theme;
+    let mut lines = vec![
+        Line::from(book.title().to_string().bold().fg(theme.accent)),
+        Line::from(""),
+    ];
+
+    let mut field = |label: &str, value: String| {
+        if !value.trim().is_empty() {
+            lines.push(Line::from(vec![
+                Span::styled(format!("{}: ", label), Style::default().fg(theme.label)),
+                Span::raw(value),
+            ]));
+        }
+    };
+
+    let people = |people: &[Contributor]| {
+        people
+            .iter()
+            .map(|person| match person.role.as_str() {
+                "" | "aut" => person.name.clone(),
+                role => format!("{} ({})", person.name, role),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    field(lang.text("作者", "Author"), people(&metadata.creators));
+    field(
+        lang.text("贡献者", "Contributors"),
+        people(&metadata.contributors),
+    );
+    if let Some(series) = &metadata.series {
+        field(
+            lang.text("系列", "Series"),
+            match series.index.as_str() {
+                "" => series.name.clone(),
+                index => format!("{} #{}", series.name, index),
+            },
+        );
+    }
+    field(lang.text("出版社", "Publisher"), metadata.publisher.clone());
+    field(lang.text("出版日期", "Published"), metadata.date.clone());
+    field(lang.text("修改日期", "Modified"), metadata.modified.clone());
+    field(lang.text("语言", "Language"), metadata.language.clone());
+    for identifier in &metadata.identifiers {
+        let name = lang.text("标识符", "Identifier");
+        let label = match identifier.scheme.as_str() {
+            "" => name.to_string(),
+            scheme => format!("{} ({})", name, scheme),
+        };
+        field(&label, identifier.value.clone());
     }
+    field(lang.text("主题", "Subjects"), metadata.subjects.join(", "));
+    field(lang.text("版权", "Rights"), metadata.rights.clone());
+    // 简介经常是 HTML
+    field(
+        lang.text("简介", "Description"),
+        StyledText::from_html(&metadata.description)
+            .to_string()
+            .replace('\n', " "),
+    );
+
+    field(
+        lang.text("文件", "File"),
+        book.source().display().to_string(),
+    );
+    field(
+        lang.text("大小", "Size"),
+        fs::metadata(book.source())
+            .map(|metadata| format_size(metadata.len()))
+            .unwrap_or_default(),
+    );
+    field(
+        lang.text("Spine 文档", "Spine documents"),
+        book.spine().len().to_string(),
+    );
+    field(
+        lang.text("大纲条目", "Outline entries"),
+        book.flat_toc().len().to_string(),
+    );
+    field(
+        lang.text("阅读进度", "Progress"),
+        format!(
+            "{:.1}% ({} {}/{} {})",
+            app.book_progress(book) * 100.0,
+            lang.text("章节", "chapter"),
+            app.selected + 1,
+            book.flat_toc().len(),
+            book.flat_toc()
+                .get(app.selected)
+                .map(|toc| toc.title.as_str())
+                .unwrap_or_default()
+        ),
+    );
+
+    lines
+}
+
+fn render_bookmarks(frame: &mut Frame, app: &App) {
+    let area = centered_rect(80, 60, frame.size());
+    let now = SystemTime::now();
+    let lang = app.config.language;
+    let theme = &app.config.theme;
+
+    let items: Vec<ListItem> = app
+        .bookmarks
+        .iter()
+        .map(|bookmark| {
+            let created = UNIX_EPOCH + Duration::from_secs(bookmark.created);
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{}  ", bookmark.name),
+                    Style::default().fg(theme.accent),
+                ),
+                Span::styled(
+                    format!("{}  {}  ", bookmark.chapter, format_age(now, created, lang)),
+                    Style::default().fg(theme.label),
+                ),
+                Span::raw(bookmark.snippet.clone()),
+            ]))
+        })
+        .collect();
+
+    let title = match items.len() {
+        0 => format!(
+            "{} {}",
+            lang.text("书签", "Bookmarks"),
+            hints(app, &[(Action::AddBookmark, "添加书签", "add one")])
+        ),
+        count => format!(
+            "{} [{}] {}",
+            lang.text("书签", "Bookmarks"),
+            count,
+            hints(
+                app,
+                &[
+                    (Action::OpenLink, "跳转", "jump"),
+                    (Action::Delete, "删除", "delete"),
+                    (Action::ClearSearch, "关闭", "close"),
+                ]
+            )
+        ),
+    };
+    let mut state = ListState::default().with_selected(Some(app.bookmark_selected));
+
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::default().title(title).borders(Borders::ALL))
+            .highlight_style(selected_style(theme)),
+        area,
+        &mut state,
+    );
+}
+
+fn render_highlights(frame: &mut Frame, app: &App) {
+    let area = centered_rect(80, 60, frame.size());
+    let lang = app.config.language;
+    let theme = &app.config.theme;
+
+    let items: Vec<ListItem> = app
+        .highlights
+        .iter()
+        .map(|highlight| {
+            let mut spans = vec![
+                Span::styled(
+                    format!("{}  ", highlight.chapter),
+                    Style::default().fg(theme.label),
+                ),
+                Span::raw(highlight.text.replace('\n', " ")),
+            ];
+            if !highlight.note.is_empty() {
+                spans.push(Span::styled(
+                    format!("  ({})", highlight.note),
+                    Style::default().fg(theme.accent),
+                ));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+
+    let title = match items.len() {
+        0 => format!(
+            "{} {}",
+            lang.text("高亮", "Highlights"),
+            hints(
+                app,
+                &[(Action::Visual, "在内容区开始选择", "select in the content")]
+            )
+        ),
+        count => format!(
+            "{} [{}] {}",
+            lang.text("高亮", "Highlights"),
+            count,
+            hints(
+                app,
+                &[
+                    (Action::OpenLink, "跳转", "jump"),
+                    (Action::Delete, "删除", "delete"),
+                    (Action::ExportMarkdown, "导出 Markdown", "export Markdown"),
+                    (Action::ExportJson, "导出 JSON", "export JSON"),
+                    (Action::ClearSearch, "关闭", "close"),
+                ]
+            )
+        ),
+    };
+    let mut state = ListState::default().with_selected(Some(app.highlight_selected));
+
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::default().title(title).borders(Borders::ALL))
+            .highlight_style(selected_style(theme)),
+        area,
+        &mut state,
+    );
+}
+
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(rows[1])[1]
+}
+
+fn selected_style(theme: &Theme) -> Style {
+    Style::default().bg(theme.selected_bg).fg(theme.selected_fg)
 }
 
 fn process_node(
     item: &Toc,
     depth: usize,
-    book: &Book,
+    selected: usize,
+    theme: &Theme,
     outlines: &mut Vec<Line>,
     index: &mut usize,
     content_title: &mut String,
@@ -123,11 +951,11 @@ fn process_node(
         item.children.iter().for_each(|child| {
             if child.title.trim() != item.title.trim() {
                 let mut select_tag = ' ';
-                let mut fg = Style::default().fg(Color::White);
-                if *index == book.selected {
+                let mut fg = Style::default().fg(theme.outline_child);
+                if *index == selected {
                     *content_title = child.title.clone();
                     select_tag = '*';
-                    fg = get_select_fg(true);
+                    fg = selected_style(theme);
                 }
                 let indent = "  ".repeat(depth); // 缩进来表示层级
                 outlines.push(Line::from(vec![Span::styled(
@@ -136,8 +964,31 @@ fn process_node(
                 )]));
                 *index += 1;
                 // 递归调用处理子节点
-                process_node(child, depth + 1, book, outlines, index, content_title);
+                process_node(
+                    child,
+                    depth + 1,
+                    selected,
+                    theme,
+                    outlines,
+                    index,
+                    content_title,
+                );
             }
         });
     }
 }
+
+// `(j 向下滚动, Esc 关闭)` with the keys the keymap binds to the actions
+fn hints(app: &App, actions: &[(Action, &'static str, &'static str)]) -> String {
+    let lang = app.config.language;
+    let hints: Vec<String> = actions
+        .iter()
+        .map(|(action, zh, en)| hint(lang, app.keymap.key(*action, KeyMode::Normal), zh, en))
+        .collect();
+
+    format!("({})", hints.join(", "))
+}
+
+fn hint(lang: Language, key: String, zh: &'static str, en: &'static str) -> String {
+    format!("{} {}", key, lang.text(zh, en))
+}
@@ -1,21 +1,15 @@
 use super::event::handle_events;
-use crate::book::Book;
-use crossterm::{
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    ExecutableCommand,
-};
-use std::io::{self, stdout};
-
-pub fn start(book: &mut Book) -> io::Result<()> {
-    enable_raw_mode()?;
-
-    stdout().execute(EnterAlternateScreen)?;
-
-    handle_events(book)?;
-
-    disable_raw_mode()?;
-
-    stdout().execute(LeaveAlternateScreen)?;
-
-    Ok(())
+use super::terminal::TerminalGuard;
+use crate::config::Config;
+use crate::explorer::index::Explorer;
+use std::io;
+use std::path::PathBuf;
+
+// Read the book of a saved index from its saved position, `cache_dir` is where the index,
+// bookmarks and highlights are saved, empty when there is none
+pub fn start(explorer: &Explorer, cache_dir: PathBuf, config: Config) -> io::Result<()> {
+    // 出错或者 panic 时也会先恢复终端
+    let _guard = TerminalGuard::new()?;
+
+    handle_events(explorer, cache_dir, config)
 }
@@ -0,0 +1,112 @@
+use crossterm::{
+    cursor::Show,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    ExecutableCommand,
+};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGTSTP};
+use signal_hook::low_level::{emulate_default_handler, unregister};
+use signal_hook::SigId;
+use std::io::{self, stdout};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+
+// 终端是否处于阅读器的状态(raw mode 和备用屏幕)
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: Once = Once::new();
+
+// Puts the terminal into raw mode and the alternate screen, and restores it when dropped or
+// when the program panics
+pub struct TerminalGuard;
+
+// Signals that arrived since the last check, the reader handles them in its event loop
+pub struct Signals {
+    terminate: Arc<AtomicBool>,
+    suspend: Arc<AtomicBool>,
+    ids: Vec<SigId>,
+}
+
+impl TerminalGuard {
+    pub fn new() -> io::Result<Self> {
+        // 先恢复终端再输出 panic 信息, 否则信息会被备用屏幕清除
+        PANIC_HOOK.call_once(|| {
+            let hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                let _ = restore();
+                hook(info);
+            }));
+        });
+
+        enter()?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = restore();
+    }
+}
+
+impl Signals {
+    // SIGTERM, SIGHUP and SIGINT ask the reader to quit, SIGTSTP to suspend
+    pub fn register() -> io::Result<Self> {
+        let terminate = Arc::new(AtomicBool::new(false));
+        let suspend = Arc::new(AtomicBool::new(false));
+
+        let mut ids = vec![];
+        for signal in [SIGTERM, SIGHUP, SIGINT] {
+            ids.push(signal_hook::flag::register(signal, Arc::clone(&terminate))?);
+        }
+        ids.push(signal_hook::flag::register(SIGTSTP, Arc::clone(&suspend))?);
+
+        Ok(Signals {
+            terminate,
+            suspend,
+            ids,
+        })
+    }
+
+    pub fn terminate(&self) -> bool {
+        self.terminate.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn suspend(&self) -> bool {
+        self.suspend.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for id in self.ids.drain(..) {
+            unregister(id);
+        }
+    }
+}
+
+// Restore the terminal, stop the process like Ctrl-Z in a shell and set the terminal up
+// again once it continues (`fg`)
+pub fn suspend() -> io::Result<()> {
+    restore()?;
+    // SIGTSTP 已经被捕获, 这里模拟默认的处理: 停止进程
+    emulate_default_handler(SIGTSTP)?;
+    enter()
+}
+
+fn enter() -> io::Result<()> {
+    enable_raw_mode()?;
+    stdout().execute(EnterAlternateScreen)?;
+    ACTIVE.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+// 可以重复调用, 只有第一次生效
+fn restore() -> io::Result<()> {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    disable_raw_mode()?;
+    stdout().execute(LeaveAlternateScreen)?.execute(Show)?;
+    Ok(())
+}